@@ -0,0 +1,529 @@
+//! Minimal ACME (RFC 8555) client.
+//!
+//! Only the HTTP-01 challenge is supported. Challenge responses are kept in memory
+//! and served by the router under `/.well-known/acme-challenge/:token`.
+
+use std::{
+	collections::HashMap,
+	fmt::Display,
+	io::{self, Write},
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::Duration,
+};
+
+use axum::{
+	extract::{Path as UrlPath, State},
+	http::StatusCode,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use ring::{
+	digest::{digest, SHA256},
+	rand::SystemRandom,
+	signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+
+use crate::config::{AcmeConfig, CONFIG};
+
+/// token -> key authorization
+pub type AcmeChallenges = Arc<RwLock<HashMap<String, String>>>;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 60;
+const RETRY_AFTER_FAILURE: Duration = Duration::from_secs(60 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60 * 12);
+
+#[derive(Debug)]
+pub enum AcmeError {
+	Http(reqwest::Error),
+	IO(io::Error),
+	/// the server returned something unexpected
+	Protocol(String),
+	Crypto(String),
+}
+
+impl From<reqwest::Error> for AcmeError {
+	fn from(err: reqwest::Error) -> Self {
+		Self::Http(err)
+	}
+}
+
+impl From<io::Error> for AcmeError {
+	fn from(err: io::Error) -> Self {
+		Self::IO(err)
+	}
+}
+
+impl Display for AcmeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Http(err) => write!(f, "http: {err}"),
+			Self::IO(err) => write!(f, "io: {err}"),
+			Self::Protocol(msg) => write!(f, "protocol: {msg}"),
+			Self::Crypto(msg) => write!(f, "crypto: {msg}"),
+		}
+	}
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+	new_nonce: String,
+	new_account: String,
+	new_order: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Order {
+	status: String,
+	authorizations: Vec<String>,
+	finalize: String,
+	certificate: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Authorization {
+	status: String,
+	challenges: Vec<Challenge>,
+}
+
+#[derive(serde::Deserialize)]
+struct Challenge {
+	#[serde(rename = "type")]
+	kind: String,
+	url: String,
+	token: String,
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+	URL_SAFE_NO_PAD.encode(data)
+}
+
+struct AcmeClient {
+	http: reqwest::Client,
+	directory: Directory,
+	key: EcdsaKeyPair,
+	rng: SystemRandom,
+	/// account url, used as `kid` once registered
+	kid: Option<String>,
+	nonce: Option<String>,
+}
+
+impl AcmeClient {
+	async fn new(config: &AcmeConfig) -> Result<Self, AcmeError> {
+		let mut http = reqwest::Client::builder().user_agent(concat!("voice/", env!("CARGO_PKG_VERSION")));
+		if let Some(ca_path) = &config.directory_ca_pem_path {
+			let ca = reqwest::Certificate::from_pem(&tokio::fs::read(ca_path).await?)?;
+			http = http.add_root_certificate(ca);
+		}
+		let http = http.build()?;
+
+		let directory = http.get(&config.directory_url).send().await?.error_for_status()?.json().await?;
+
+		let rng = SystemRandom::new();
+		let key = load_or_create_account_key(&config.account_key_path, &rng).await?;
+
+		Ok(Self {
+			http,
+			directory,
+			key,
+			rng,
+			kid: None,
+			nonce: None,
+		})
+	}
+
+	fn jwk(&self) -> Value {
+		// uncompressed point: 0x04 || x || y
+		let public_key = self.key.public_key().as_ref();
+		json!({
+			"crv": "P-256",
+			"kty": "EC",
+			"x": b64(&public_key[1..33]),
+			"y": b64(&public_key[33..65]),
+		})
+	}
+
+	/// RFC 7638 thumbprint, members in lexicographic order without whitespace
+	fn thumbprint(&self) -> String {
+		let jwk = self.jwk();
+		let canonical = format!(
+			r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+			jwk["x"].as_str().unwrap(),
+			jwk["y"].as_str().unwrap()
+		);
+		b64(digest(&SHA256, canonical.as_bytes()))
+	}
+
+	async fn nonce(&mut self) -> Result<String, AcmeError> {
+		if let Some(nonce) = self.nonce.take() {
+			return Ok(nonce);
+		}
+		let response = self.http.head(&self.directory.new_nonce).send().await?;
+		replay_nonce(&response).ok_or(AcmeError::Protocol("newNonce returned no nonce".to_string()))
+	}
+
+	/// Sends a JWS signed request. `None` payload makes a POST-as-GET.
+	async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response, AcmeError> {
+		// a nonce may be rejected, in which case the error carries a fresh one
+		for _ in 0..3 {
+			let mut protected = json!({
+				"alg": "ES256",
+				"nonce": self.nonce().await?,
+				"url": url,
+			});
+			match &self.kid {
+				Some(kid) => protected["kid"] = json!(kid),
+				None => protected["jwk"] = self.jwk(),
+			}
+
+			let protected = b64(protected.to_string());
+			let payload = payload.map(|x| b64(x.to_string())).unwrap_or_default();
+			let signature = self
+				.key
+				.sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+				.map_err(|_| AcmeError::Crypto("failed to sign request".to_string()))?;
+			let body = json!({
+				"protected": protected,
+				"payload": payload,
+				"signature": b64(signature),
+			});
+
+			let response = self
+				.http
+				.post(url)
+				.header(CONTENT_TYPE, "application/jose+json")
+				.body(body.to_string())
+				.send()
+				.await?;
+			self.nonce = replay_nonce(&response);
+
+			if response.status().is_success() {
+				return Ok(response);
+			}
+
+			let status = response.status();
+			let problem = response.text().await.unwrap_or_default();
+			if problem.contains("urn:ietf:params:acme:error:badNonce") {
+				tracing::debug!("acme: bad nonce, retrying");
+				continue;
+			}
+			return Err(AcmeError::Protocol(format!("{url} returned {status}: {problem}")));
+		}
+		Err(AcmeError::Protocol(format!("{url} kept rejecting nonces")))
+	}
+
+	async fn register(&mut self, contact_email: Option<&str>) -> Result<(), AcmeError> {
+		let mut payload = json!({ "termsOfServiceAgreed": true });
+		if let Some(email) = contact_email {
+			payload["contact"] = json!([format!("mailto:{email}")]);
+		}
+		let url = self.directory.new_account.clone();
+		let response = self.post(&url, Some(&payload)).await?;
+		let kid = location(&response)?;
+		tracing::debug!("acme: using account {}", kid);
+		self.kid = Some(kid);
+		Ok(())
+	}
+
+	async fn poll<T: serde::de::DeserializeOwned>(
+		&mut self,
+		url: &str,
+		mut done: impl FnMut(&T) -> Result<bool, AcmeError>,
+	) -> Result<T, AcmeError> {
+		for _ in 0..POLL_ATTEMPTS {
+			let value: T = self.post(url, None).await?.json().await?;
+			if done(&value)? {
+				return Ok(value);
+			}
+			tokio::time::sleep(POLL_INTERVAL).await;
+		}
+		Err(AcmeError::Protocol(format!("timed out waiting for {url}")))
+	}
+
+	/// Runs the full order flow and returns the certificate chain and key in pem format
+	async fn order(&mut self, domains: &[String], challenges: &AcmeChallenges) -> Result<(String, String), AcmeError> {
+		let identifiers = domains.iter().map(|x| json!({ "type": "dns", "value": x })).collect::<Vec<_>>();
+		let url = self.directory.new_order.clone();
+		let response = self.post(&url, Some(&json!({ "identifiers": identifiers }))).await?;
+		let order_url = location(&response)?;
+		let order: Order = response.json().await?;
+
+		let thumbprint = self.thumbprint();
+		let mut tokens = Vec::new();
+		let result = async {
+			for authorization_url in &order.authorizations {
+				let authorization: Authorization = self.post(authorization_url, None).await?.json().await?;
+				if authorization.status == "valid" {
+					continue;
+				}
+				let challenge = authorization
+					.challenges
+					.iter()
+					.find(|x| x.kind == "http-01")
+					.ok_or(AcmeError::Protocol("no http-01 challenge offered".to_string()))?;
+
+				challenges.write().await.insert(challenge.token.clone(), format!("{}.{thumbprint}", challenge.token));
+				tokens.push(challenge.token.clone());
+
+				self.post(&challenge.url, Some(&json!({}))).await?;
+				self.poll(authorization_url, |x: &Authorization| match x.status.as_str() {
+					"valid" => Ok(true),
+					"pending" | "processing" => Ok(false),
+					status => Err(AcmeError::Protocol(format!("authorization is {status}"))),
+				})
+				.await?;
+			}
+
+			let mut params = rcgen::CertificateParams::new(domains.to_vec());
+			params.distinguished_name = rcgen::DistinguishedName::new();
+			let certificate = rcgen::Certificate::from_params(params).map_err(|x| AcmeError::Crypto(x.to_string()))?;
+			let csr = certificate.serialize_request_der().map_err(|x| AcmeError::Crypto(x.to_string()))?;
+
+			let order = self
+				.poll(&order_url, |x: &Order| match x.status.as_str() {
+					"ready" | "valid" => Ok(true),
+					"pending" | "processing" => Ok(false),
+					status => Err(AcmeError::Protocol(format!("order is {status}"))),
+				})
+				.await?;
+			if order.status == "ready" {
+				self.post(&order.finalize, Some(&json!({ "csr": b64(csr) }))).await?;
+			}
+			let order = self
+				.poll(&order_url, |x: &Order| match x.status.as_str() {
+					"valid" => Ok(true),
+					"ready" | "processing" => Ok(false),
+					status => Err(AcmeError::Protocol(format!("order is {status}"))),
+				})
+				.await?;
+
+			let certificate_url =
+				order.certificate.ok_or(AcmeError::Protocol("valid order has no certificate".to_string()))?;
+			let chain = self.post(&certificate_url, None).await?.text().await?;
+
+			Ok((chain, certificate.serialize_private_key_pem()))
+		}
+		.await;
+
+		let mut challenges_lock = challenges.write().await;
+		for token in tokens {
+			challenges_lock.remove(&token);
+		}
+
+		result
+	}
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+	response.headers().get("Replay-Nonce").and_then(|x| x.to_str().ok()).map(str::to_string)
+}
+
+fn location(response: &reqwest::Response) -> Result<String, AcmeError> {
+	response
+		.headers()
+		.get(LOCATION)
+		.and_then(|x| x.to_str().ok())
+		.map(str::to_string)
+		.ok_or(AcmeError::Protocol(format!("{} returned no location", response.url())))
+}
+
+async fn load_or_create_account_key(path: &Path, rng: &SystemRandom) -> Result<EcdsaKeyPair, AcmeError> {
+	let pkcs8 = match tokio::fs::read(path).await {
+		Ok(pkcs8) => pkcs8,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => {
+			tracing::info!("acme: creating account key {}", path.display());
+			let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, rng)
+				.map_err(|_| AcmeError::Crypto("failed to generate account key".to_string()))?;
+			if let Some(parent) = path.parent() {
+				tokio::fs::create_dir_all(parent).await?;
+			}
+			let staged = stage_file(path, pkcs8.as_ref(), PRIVATE_KEY_MODE).await?;
+			tokio::fs::rename(staged, path).await?;
+			pkcs8.as_ref().to_vec()
+		}
+		Err(err) => return Err(err.into()),
+	};
+	EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, rng)
+		.map_err(|x| AcmeError::Crypto(format!("invalid account key {}: {x}", path.display())))
+}
+
+/// Only readable by the owner
+const PRIVATE_KEY_MODE: u32 = 0o600;
+
+/// Write `data` to a new file next to `path` with `mode`, to be renamed over `path`.
+/// Returns the new file's path
+async fn stage_file(path: &Path, data: &[u8], mode: u32) -> io::Result<PathBuf> {
+	let mut staged = path.as_os_str().to_owned();
+	staged.push(".tmp");
+	let staged = PathBuf::from(staged);
+	let data = data.to_vec();
+
+	tokio::task::spawn_blocking(move || {
+		// the mode only applies to new files
+		match std::fs::remove_file(&staged) {
+			Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+			_ => {}
+		}
+		let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(&staged)?;
+		file.write_all(&data)?;
+		file.sync_all()?;
+		Ok(staged)
+	})
+	.await?
+}
+
+/// Time left until the certificate at `path` expires.
+/// `None` if it doesn't exist or can't be parsed.
+pub fn certificate_validity_left(path: &Path) -> Option<time::Duration> {
+	let pem = std::fs::read(path).ok()?;
+	let (_, pem) = x509_parser::pem::parse_x509_pem(&pem).ok()?;
+	let certificate = pem.parse_x509().ok()?;
+	let not_after = certificate.validity().not_after.to_datetime();
+	Some(not_after - time::OffsetDateTime::now_utc())
+}
+
+/// Self-signed certificate to serve until the first real one is issued
+pub fn placeholder_certificate(domains: &[String]) -> Result<(Vec<u8>, Vec<u8>), AcmeError> {
+	let certificate = rcgen::generate_simple_self_signed(domains.to_vec()).map_err(|x| AcmeError::Crypto(x.to_string()))?;
+	let cert_pem = certificate.serialize_pem().map_err(|x| AcmeError::Crypto(x.to_string()))?;
+	Ok((cert_pem.into_bytes(), certificate.serialize_private_key_pem().into_bytes()))
+}
+
+async fn renew(tls_config: &RustlsConfig, challenges: &AcmeChallenges) -> Result<(), AcmeError> {
+	let (acme_config, cert_pem_path, key_pem_path) = {
+		let config_lock = CONFIG.read().await;
+		(config_lock.acme.clone(), config_lock.cert_pem_path.clone(), config_lock.key_pem_path.clone())
+	};
+
+	let renew_before = time::Duration::days(acme_config.renew_days_before_expiry as i64);
+	match certificate_validity_left(&cert_pem_path) {
+		Some(left) if left > renew_before => {
+			tracing::debug!("acme: certificate valid for {} more days", left.whole_days());
+			return Ok(());
+		}
+		Some(left) => tracing::info!("acme: certificate expires in {} days, renewing", left.whole_days()),
+		None => tracing::info!("acme: no usable certificate at {}, requesting one", cert_pem_path.display()),
+	}
+
+	let mut client = AcmeClient::new(&acme_config).await?;
+	client.register(acme_config.contact_email.as_deref()).await?;
+	let (cert_pem, key_pem) = client.order(&acme_config.domains, challenges).await?;
+
+	for path in [&cert_pem_path, &key_pem_path] {
+		if let Some(parent) = path.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+	}
+	// both are written in full before either is replaced, so the reloader doesn't see half a file
+	let staged_key = stage_file(&key_pem_path, key_pem.as_bytes(), PRIVATE_KEY_MODE).await?;
+	let staged_cert = stage_file(&cert_pem_path, cert_pem.as_bytes(), 0o644).await?;
+	tokio::fs::rename(staged_key, &key_pem_path).await?;
+	tokio::fs::rename(staged_cert, &cert_pem_path).await?;
+
+	tls_config.reload_from_pem(cert_pem.into_bytes(), key_pem.into_bytes()).await?;
+	tracing::info!("acme: installed new certificate for {}", acme_config.domains.join(", "));
+	Ok(())
+}
+
+/// Obtain a certificate if there's none, then keep renewing it in the background
+pub fn spawn_renewal(tls_config: RustlsConfig, challenges: AcmeChallenges) {
+	tokio::task::spawn(async move {
+		loop {
+			let sleep_for = match renew(&tls_config, &challenges).await {
+				Ok(()) => CHECK_INTERVAL,
+				Err(err) => {
+					tracing::error!("acme: failed to renew certificate: {}", err);
+					RETRY_AFTER_FAILURE
+				}
+			};
+			tokio::time::sleep(sleep_for).await;
+		}
+	});
+}
+
+pub async fn serve_challenge(
+	State(challenges): State<AcmeChallenges>,
+	UrlPath(token): UrlPath<String>,
+) -> Result<String, StatusCode> {
+	challenges.read().await.get(&token).cloned().ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+
+	use super::*;
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("voice-acme-{}-{name}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[tokio::test]
+	async fn staged_key_is_private() {
+		let path = temp_dir("stage").join("key.pem");
+		std::fs::write(&path, "old").unwrap();
+
+		let staged = stage_file(&path, b"new", PRIVATE_KEY_MODE).await.unwrap();
+		// not replaced until renamed
+		assert_eq!(std::fs::read(&path).unwrap(), b"old");
+		assert_eq!(std::fs::metadata(&staged).unwrap().permissions().mode() & 0o777, PRIVATE_KEY_MODE);
+
+		tokio::fs::rename(&staged, &path).await.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"new");
+		assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, PRIVATE_KEY_MODE);
+	}
+
+	#[tokio::test]
+	async fn account_key_is_private_and_reused() {
+		let path = temp_dir("account").join("account.pk8");
+		let rng = SystemRandom::new();
+		let key = load_or_create_account_key(&path, &rng).await.unwrap();
+		assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, PRIVATE_KEY_MODE);
+
+		let loaded = load_or_create_account_key(&path, &rng).await.unwrap();
+		assert_eq!(key.public_key().as_ref(), loaded.public_key().as_ref());
+	}
+
+	/// Issues a certificate from a local [Pebble](https://github.com/letsencrypt/pebble) test CA.
+	/// Challenges aren't served to pebble here, so it has to skip validation:
+	///
+	/// ```sh
+	/// PEBBLE_VA_ALWAYS_VALID=1 pebble -config test/config/pebble-config.json
+	/// PEBBLE_DIRECTORY_URL=https://localhost:14000/dir PEBBLE_CA_PEM=test/certs/pebble.minica.pem \
+	/// 	cargo test pebble -- --ignored
+	/// ```
+	#[tokio::test]
+	#[ignore = "needs a running pebble, see the doc comment"]
+	async fn pebble_issues_certificate() {
+		let dir = temp_dir("pebble");
+		let config = AcmeConfig {
+			enabled: true,
+			directory_url: std::env::var("PEBBLE_DIRECTORY_URL").expect("PEBBLE_DIRECTORY_URL not set"),
+			domains: vec!["voice.test".to_string()],
+			contact_email: Some("admin@voice.test".to_string()),
+			account_key_path: dir.join("account.pk8"),
+			renew_days_before_expiry: 30,
+			directory_ca_pem_path: std::env::var_os("PEBBLE_CA_PEM").map(PathBuf::from),
+		};
+		let challenges = AcmeChallenges::default();
+
+		let mut client = AcmeClient::new(&config).await.unwrap();
+		client.register(config.contact_email.as_deref()).await.unwrap();
+		let (cert_pem, key_pem) = client.order(&config.domains, &challenges).await.unwrap();
+
+		// answered challenges are cleaned up
+		assert!(challenges.read().await.is_empty());
+		assert!(key_pem.contains("PRIVATE KEY"));
+		let cert_path = dir.join("cert.pem");
+		std::fs::write(&cert_path, cert_pem).unwrap();
+		assert!(certificate_validity_left(&cert_path).is_some_and(|x| x.is_positive()));
+	}
+}
@@ -0,0 +1,269 @@
+use std::{
+	collections::HashMap,
+	io::{self, Read},
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use tokio::{
+	sync::{Mutex, Semaphore},
+	task::JoinSet,
+};
+
+use crate::{
+	avg::DisplayDuration,
+	ffmpeg::{FFmpeg, FFmpegError},
+};
+
+/// Kept in the output directory, maps output paths to the hash of the input they were made from
+const MANIFEST_FILE_NAME: &str = ".voice-batch.json";
+
+type Manifest = HashMap<PathBuf, String>;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "result")]
+pub enum FileResult {
+	Processed {
+		duration_before: f32,
+		duration_after: f32,
+		processing_time: f32,
+	},
+	/// output is up to date with the input's content
+	Skipped,
+	Failed {
+		error: String,
+	},
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FileReport {
+	pub input: PathBuf,
+	pub output: PathBuf,
+	#[serde(flatten)]
+	pub result: FileResult,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BatchReport {
+	pub processed: usize,
+	pub skipped: usize,
+	pub failed: usize,
+	/// of the processed files, in seconds
+	pub duration_before: f32,
+	pub duration_after: f32,
+	pub files: Vec<FileReport>,
+}
+
+impl BatchReport {
+	fn push(&mut self, file: FileReport) {
+		match file.result {
+			FileResult::Processed {
+				duration_before,
+				duration_after,
+				..
+			} => {
+				self.processed += 1;
+				self.duration_before += duration_before;
+				self.duration_after += duration_after;
+			}
+			FileResult::Skipped => self.skipped += 1,
+			FileResult::Failed { .. } => self.failed += 1,
+		}
+		self.files.push(file);
+	}
+}
+
+/// All files under `dir`, skipping hidden ones and the directory `skip`, relative to `dir`
+fn collect_files(dir: &Path, relative: &Path, skip: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+	for entry in dir.join(relative).read_dir()? {
+		let entry = entry?;
+		if entry.file_name().to_string_lossy().starts_with('.') {
+			continue;
+		}
+		let path = relative.join(entry.file_name());
+		if entry.file_type()?.is_dir() {
+			// the output dir inside the input dir, its files are results of an earlier run
+			if entry.path().canonicalize()? == skip {
+				continue;
+			}
+			collect_files(dir, &path, skip, files)?;
+		} else {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+/// sha256 of the file, read in chunks since recordings can be large
+async fn hash_file(path: PathBuf) -> io::Result<String> {
+	tokio::task::spawn_blocking(move || {
+		let mut file = std::fs::File::open(path)?;
+		let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+		let mut buf = vec![0; 1 << 16];
+		loop {
+			let n = file.read(&mut buf)?;
+			if n == 0 {
+				break;
+			}
+			context.update(&buf[..n]);
+		}
+		Ok(context.finish().as_ref().iter().map(|x| format!("{x:02x}")).collect())
+	})
+	.await?
+}
+
+async fn load_manifest(output_dir: &Path) -> Manifest {
+	match tokio::fs::read(output_dir.join(MANIFEST_FILE_NAME)).await {
+		Ok(x) => serde_json::from_slice(&x).unwrap_or_else(|err| {
+			eprintln!("warning: ignoring invalid {MANIFEST_FILE_NAME}: {err}");
+			Manifest::new()
+		}),
+		Err(_) => Manifest::new(),
+	}
+}
+
+async fn save_manifest(output_dir: &Path, manifest: &Manifest) {
+	let json = serde_json::to_vec_pretty(manifest).unwrap();
+	if let Err(err) = tokio::fs::write(output_dir.join(MANIFEST_FILE_NAME), json).await {
+		eprintln!("warning: failed to write {MANIFEST_FILE_NAME}: {err}");
+	}
+}
+
+/// Remove silence from a single file, returning the durations before and after
+async fn process_file(input: &Path, output: &Path, ffmpeg_executable: &Path) -> Result<(f32, f32), FFmpegError> {
+	if let Some(parent) = output.parent() {
+		tokio::fs::create_dir_all(parent).await?;
+	}
+	// written under another name first, so an interrupted run doesn't leave a truncated output behind
+	let partial_output = output.with_extension("mp4.part");
+
+	let ffmpeg = FFmpeg::new(input.to_path_buf(), partial_output.clone(), ffmpeg_executable.to_path_buf());
+	let duration = ffmpeg.probe_duration().await?;
+	let analysis = ffmpeg.analyze_silence(duration, |_| {}).await?;
+	let duration_after = analysis.audible.iter().map(|x| x.end - x.start).sum::<f32>();
+
+	let child = ffmpeg.spawn_remove_silence(&analysis.audible).await?;
+	// also drains the progress output
+	let result = child.wait_with_output().await?;
+	if !result.status.success() {
+		let _ = tokio::fs::remove_file(&partial_output).await;
+		return Err(FFmpegError::FFmpeg(String::from_utf8_lossy(&result.stderr).to_string()));
+	}
+	tokio::fs::rename(&partial_output, output).await?;
+
+	Ok((analysis.duration.as_secs_f32(), duration_after))
+}
+
+/// Process every file under `input_dir` with at most `jobs` running at once,
+/// writing the results to the same relative paths under `output_dir`, as mp4.
+pub async fn run(
+	input_dir: PathBuf,
+	output_dir: PathBuf,
+	jobs: usize,
+	ffmpeg_executable: PathBuf,
+) -> io::Result<BatchReport> {
+	tokio::fs::create_dir_all(&output_dir).await?;
+	let mut files = Vec::new();
+	collect_files(&input_dir, Path::new(""), &output_dir.canonicalize()?, &mut files)?;
+	files.sort();
+
+	// e.g. `a.mov` and `a.mkv` would both be written to `a.mp4`
+	let mut by_output = HashMap::<PathBuf, Vec<PathBuf>>::new();
+	for relative in &files {
+		by_output.entry(relative.with_extension("mp4")).or_default().push(relative.clone());
+	}
+
+	let total = files.len();
+	let manifest = Arc::new(Mutex::new(load_manifest(&output_dir).await));
+	let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+	let ffmpeg_executable = Arc::new(ffmpeg_executable);
+
+	let mut join_set = JoinSet::new();
+	for relative in files {
+		let input = input_dir.join(&relative);
+		let output_relative = relative.with_extension("mp4");
+		let output = output_dir.join(&output_relative);
+		let (manifest, semaphore, ffmpeg_executable, output_dir) =
+			(manifest.clone(), semaphore.clone(), ffmpeg_executable.clone(), output_dir.clone());
+		let collisions = by_output[&output_relative]
+			.iter()
+			.filter(|x| **x != relative)
+			.map(|x| x.display().to_string())
+			.collect::<Vec<_>>();
+
+		join_set.spawn(async move {
+			let _permit = semaphore.acquire_owned().await.unwrap();
+
+			let result = async {
+				if !collisions.is_empty() {
+					return Err(FFmpegError::FFmpeg(format!(
+						"{} would also be written by {}, rename one of them",
+						output.display(),
+						collisions.join(", ")
+					)));
+				}
+				let hash = hash_file(input.clone()).await?;
+				let up_to_date = manifest.lock().await.get(&output_relative) == Some(&hash);
+				if up_to_date && tokio::fs::try_exists(&output).await.unwrap_or(false) {
+					return Ok(FileResult::Skipped);
+				}
+
+				let start = Instant::now();
+				// run separately, so a panic in the ffmpeg output parsing only fails this file
+				let (duration_before, duration_after) = tokio::task::spawn({
+					let (input, output) = (input.clone(), output.clone());
+					async move { process_file(&input, &output, &ffmpeg_executable).await }
+				})
+				.await
+				.map_err(|err| FFmpegError::FFmpeg(format!("failed to process file: {err}")))??;
+
+				let mut manifest_lock = manifest.lock().await;
+				manifest_lock.insert(output_relative, hash);
+				save_manifest(&output_dir, &manifest_lock).await;
+
+				Ok(FileResult::Processed {
+					duration_before,
+					duration_after,
+					processing_time: start.elapsed().as_secs_f32(),
+				})
+			}
+			.await
+			.unwrap_or_else(|err: FFmpegError| FileResult::Failed {
+				error: err.to_string().trim().to_string(),
+			});
+
+			FileReport { input, output, result }
+		});
+	}
+
+	let mut report = BatchReport::default();
+	while let Some(file) = join_set.join_next().await {
+		let file = file.expect("batch worker panicked");
+		let done = report.files.len() + 1;
+		match &file.result {
+			FileResult::Processed {
+				duration_before,
+				duration_after,
+				processing_time,
+			} => println!(
+				"[{done}/{total}] done {}: {} -> {} in {}",
+				file.input.display(),
+				DisplayDuration(Duration::from_secs_f32(*duration_before)),
+				DisplayDuration(Duration::from_secs_f32(*duration_after)),
+				DisplayDuration(Duration::from_secs_f32(*processing_time)),
+			),
+			FileResult::Skipped => println!("[{done}/{total}] skipped {}: up to date", file.input.display()),
+			FileResult::Failed { error } => println!(
+				"[{done}/{total}] failed {}: {}",
+				file.input.display(),
+				error.lines().last().unwrap_or_default()
+			),
+		}
+		report.push(file);
+	}
+	report.files.sort_by(|a, b| a.input.cmp(&b.input));
+
+	Ok(report)
+}
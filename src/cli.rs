@@ -0,0 +1,347 @@
+use std::{io::Write, path::PathBuf, process::ExitCode, time::Duration};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::{
+	acme,
+	avg::DisplayDuration,
+	batch,
+	config::{self, ConfigOverride, LogLevel},
+	ffmpeg::FFmpeg,
+	task::{Task, TaskStatus},
+};
+
+/// Removes silence from videos.
+///
+/// Every config value can also be set with a `VOICE_<KEY>` environment variable,
+/// nested keys are separated by `__`, e.g. `VOICE_RATE_LIMIT__ENABLED=false`.
+/// Command line flags take precedence over environment variables,
+/// which take precedence over the config file.
+#[derive(Debug, clap::Parser)]
+#[command(version, author)]
+pub struct Args {
+	#[command(subcommand)]
+	pub command: Option<Command>,
+	/// config file to read, created with defaults if missing
+	#[arg(short, long, global = true, env = "VOICE_CONFIG", default_value = "config.toml")]
+	pub config: PathBuf,
+	/// port to bind to
+	#[arg(long, global = true)]
+	pub port: Option<u16>,
+	/// incoming file storage
+	#[arg(long, global = true)]
+	pub inputs_dir: Option<PathBuf>,
+	/// encoding result storage
+	#[arg(long, global = true)]
+	pub outputs_dir: Option<PathBuf>,
+	/// log file dir
+	#[arg(long, global = true)]
+	pub log_file_root: Option<PathBuf>,
+	/// web file root
+	#[arg(long, global = true)]
+	pub web_root: Option<PathBuf>,
+	/// level to log at
+	#[arg(long, global = true, value_enum)]
+	pub log_level: Option<LogLevel>,
+	/// print the effective config, after applying overrides, and exit
+	#[arg(long, global = true)]
+	pub print_config: bool,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+	/// run the server, the default
+	Serve,
+	/// remove silence from a video locally, without the server
+	Process {
+		input: PathBuf,
+		/// where to write the result, as mp4
+		#[arg(short, long)]
+		output: PathBuf,
+	},
+	/// print the audible parts of a video
+	Analyze { input: PathBuf },
+	/// process every file in a directory, skipping the ones already processed
+	Batch {
+		input_dir: PathBuf,
+		/// where to write the results, mirroring the input directory
+		#[arg(short, long)]
+		output_dir: PathBuf,
+		/// how many files to process at once, defaults to the number of cpus
+		#[arg(short, long)]
+		jobs: Option<usize>,
+		/// also write the summary to this file, as json
+		#[arg(long)]
+		report: Option<PathBuf>,
+	},
+	/// validate the config, ffmpeg and the certificate, and exit
+	Check,
+}
+
+impl Args {
+	/// Config overrides given on the command line
+	pub fn config_overrides(&self) -> Vec<ConfigOverride> {
+		let path = |x: &PathBuf| toml::Value::String(x.to_string_lossy().to_string());
+
+		let mut overrides = Vec::new();
+		let mut push = |flag: &str, key: &str, value: toml::Value| {
+			overrides.push(ConfigOverride {
+				origin: flag.to_string(),
+				key: key.to_string(),
+				value,
+				raw: None,
+			})
+		};
+		if let Some(port) = self.port {
+			push("--port", "port", toml::Value::Integer(port.into()));
+		}
+		if let Some(dir) = &self.inputs_dir {
+			push("--inputs-dir", "inputs_dir", path(dir));
+		}
+		if let Some(dir) = &self.outputs_dir {
+			push("--outputs-dir", "outputs_dir", path(dir));
+		}
+		if let Some(dir) = &self.log_file_root {
+			push("--log-file-root", "log_file_root", path(dir));
+		}
+		if let Some(dir) = &self.web_root {
+			push("--web-root", "web_root", path(dir));
+		}
+		if let Some(level) = self.log_level {
+			push("--log-level", "log_level", toml::Value::try_from(level).unwrap());
+		}
+		overrides
+	}
+}
+
+fn print_issues(warnings: &[config::ConfigIssue], errors: &[config::ConfigIssue]) {
+	for warning in warnings {
+		eprintln!("warning: {warning}");
+	}
+	for error in errors {
+		eprintln!("error: {error}");
+	}
+}
+
+/// Print the effective config without validating it
+pub fn print_config() -> ExitCode {
+	match config::load_config() {
+		Ok(loaded) => {
+			print_issues(&loaded.warnings, &[]);
+			print!("{}", toml::to_string(&loaded.config).expect("failed to serialize config to toml"));
+			ExitCode::SUCCESS
+		}
+		Err(errors) => {
+			print_issues(&[], &errors);
+			ExitCode::FAILURE
+		}
+	}
+}
+
+/// Load the config for the offline commands, which only need ffmpeg
+fn offline_config() -> Option<config::Config> {
+	let config = match config::load_config() {
+		Ok(loaded) => {
+			print_issues(&loaded.warnings, &[]);
+			loaded.config
+		}
+		Err(errors) => {
+			print_issues(&[], &errors);
+			return None;
+		}
+	};
+	if !config.encoder_found() {
+		eprintln!("error: ffmpeg not found at {}", config.ffmpeg_executable.display());
+		return None;
+	}
+	Some(config)
+}
+
+pub async fn process(input: PathBuf, output: PathBuf) -> ExitCode {
+	let Some(config) = offline_config() else {
+		return ExitCode::FAILURE;
+	};
+
+	let task = match Task::new(
+		input,
+		output.clone(),
+		Task::gen_id(),
+		config.ffmpeg_executable.clone(),
+		config.progress_interval(),
+		"cli".to_string(),
+		None,
+	) {
+		Ok(x) => x,
+		Err(err) => {
+			eprintln!("error: {err}");
+			return ExitCode::FAILURE;
+		}
+	};
+	let mut updates = task.subscribe();
+
+	let mut status = task.last_status();
+	while let TaskStatus::InProgress {
+		phase,
+		progress,
+		speed,
+		phase_eta_seconds,
+		..
+	} = status
+	{
+		let eta =
+			phase_eta_seconds.map_or("?".to_string(), |x| DisplayDuration(Duration::from_secs_f32(x)).to_string());
+		// pad to overwrite a longer previous line
+		print!("\r{phase:<10} {:5.1}% at {speed:.2}x, eta {eta:<12}", progress * 100.0);
+		let _ = std::io::stdout().flush();
+		// the sender lives as long as `task`
+		let _ = updates.changed().await;
+		status = updates.borrow_and_update().status.clone();
+	}
+	println!();
+
+	match status {
+		TaskStatus::Error(err) => {
+			eprintln!("error: {err}");
+			ExitCode::FAILURE
+		}
+		TaskStatus::Completed { summary, .. } => {
+			println!(
+				"wrote {}: {} -> {}, {:.1}% removed in {} cuts, took {}",
+				output.display(),
+				DisplayDuration(Duration::from_secs_f32(summary.original_duration)),
+				DisplayDuration(Duration::from_secs_f32(summary.output_duration)),
+				summary.removed_percent,
+				summary.cuts,
+				DisplayDuration(Duration::from_secs_f32(summary.processing_time)),
+			);
+			ExitCode::SUCCESS
+		}
+		_ => {
+			println!("wrote {}", output.display());
+			ExitCode::SUCCESS
+		}
+	}
+}
+
+pub async fn analyze(input: PathBuf) -> ExitCode {
+	let Some(config) = offline_config() else {
+		return ExitCode::FAILURE;
+	};
+
+	// output is unused by the analysis
+	let ffmpeg = FFmpeg::new(input, PathBuf::new(), config.ffmpeg_executable);
+	let analysis = async {
+		let duration = ffmpeg.probe_duration().await?;
+		ffmpeg.analyze_silence(duration, |_| {}).await
+	};
+	let analysis = match analysis.await {
+		Ok(x) => x,
+		Err(err) => {
+			eprintln!("error: {err}");
+			return ExitCode::FAILURE;
+		}
+	};
+
+	let duration = analysis.duration.as_secs_f32();
+	let audible = analysis.audible.iter().map(|x| x.end - x.start).sum::<f32>();
+	for range in &analysis.audible {
+		println!("{:10.2}s - {:10.2}s", range.start, range.end);
+	}
+	println!(
+		"{} audible parts, {audible:.2}s of {duration:.2}s, {:.1}% silent",
+		analysis.audible.len(),
+		(1.0 - audible / duration) * 100.0
+	);
+	ExitCode::SUCCESS
+}
+
+pub async fn batch(
+	input_dir: PathBuf,
+	output_dir: PathBuf,
+	jobs: Option<usize>,
+	report_path: Option<PathBuf>,
+) -> ExitCode {
+	let Some(config) = offline_config() else {
+		return ExitCode::FAILURE;
+	};
+	let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()));
+
+	let report = match batch::run(input_dir, output_dir, jobs, config.ffmpeg_executable).await {
+		Ok(x) => x,
+		Err(err) => {
+			eprintln!("error: {err}");
+			return ExitCode::FAILURE;
+		}
+	};
+
+	println!();
+	println!("processed: {}, skipped: {}, failed: {}", report.processed, report.skipped, report.failed);
+	if report.processed > 0 {
+		println!(
+			"duration: {} -> {}, {:.1}% removed",
+			DisplayDuration(Duration::from_secs_f32(report.duration_before)),
+			DisplayDuration(Duration::from_secs_f32(report.duration_after)),
+			(1.0 - report.duration_after / report.duration_before) * 100.0
+		);
+	}
+	for file in &report.files {
+		if let batch::FileResult::Failed { error } = &file.result {
+			println!("failed: {}\n{error}", file.input.display());
+		}
+	}
+
+	if let Some(report_path) = report_path {
+		let json = serde_json::to_vec_pretty(&report).unwrap();
+		if let Err(err) = tokio::fs::write(&report_path, json).await {
+			eprintln!("error: failed to write report to {}: {err}", report_path.display());
+			return ExitCode::FAILURE;
+		}
+	}
+
+	if report.failed > 0 {
+		ExitCode::FAILURE
+	} else {
+		ExitCode::SUCCESS
+	}
+}
+
+/// Same checks as on startup, plus loading the certificate. Doesn't write the default config or create dirs
+pub async fn check() -> ExitCode {
+	let config = match config::load_config() {
+		Ok(loaded) => {
+			let errors = loaded.validate();
+			print_issues(&loaded.warnings, &errors);
+			if !errors.is_empty() {
+				return ExitCode::FAILURE;
+			}
+			loaded.config
+		}
+		Err(errors) => {
+			print_issues(&[], &errors);
+			return ExitCode::FAILURE;
+		}
+	};
+	println!("config ok");
+	println!("ffmpeg found at {}", config.ffmpeg_executable.display());
+
+	if config.tls {
+		let validity_left = acme::certificate_validity_left(&config.cert_pem_path);
+		if config.acme.enabled && validity_left.is_none() {
+			println!("no certificate yet, acme will issue one on startup");
+			return ExitCode::SUCCESS;
+		}
+		if let Err(err) = RustlsConfig::from_pem_file(&config.cert_pem_path, &config.key_pem_path).await {
+			eprintln!("error: failed to load certificate: {err}");
+			return ExitCode::FAILURE;
+		}
+		match validity_left {
+			Some(left) if left.is_positive() => println!("certificate ok, expires in {} days", left.whole_days()),
+			Some(_) => {
+				eprintln!("error: certificate {} has expired", config.cert_pem_path.display());
+				return ExitCode::FAILURE;
+			}
+			None => println!("certificate loaded, but its expiry date could not be read"),
+		}
+	}
+	ExitCode::SUCCESS
+}
@@ -1,28 +1,399 @@
 use std::{
+	fmt::Display,
+	net::{IpAddr, Ipv4Addr},
 	path::{Path, PathBuf},
+	str::FromStr,
 	sync::OnceLock,
+	time::Duration,
 };
 use tokio::sync::RwLock;
 
-const CONFIG_PATH: &str = "config.toml";
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Environment variables starting with this override config values
+const ENV_PREFIX: &str = "VOICE_";
+
+/// Fields that are only read on startup, changing them requires a restart
+const RESTART_REQUIRED_FIELDS: &[&str] = &[
+	"log_level",
+	"log_file_root",
+	"port",
+	"bind_address",
+	"tls",
+	"http_redirect_port",
+	"acme.enabled",
+	"storage.backend",
+	"storage.s3",
+];
 
 /// Returned by [`reload_config`].
-/// [`ConfigReloadResult::Ok`] - Config has been updated or written or initialized
+/// [`ConfigReloadResult::Ok`] - Config has been updated or written or initialized.
+/// Contains the dotted paths of the fields that changed and non-fatal issues.
 ///
-/// [`ConfigReloadResult::Err`] - Some error was encountered. This
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`ConfigReloadResult::Err`] - The file could not be read, parsed or failed validation.
+/// The current config is left untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConfigReloadResult {
-	Ok,
-	Err,
+	Ok {
+		changed: Vec<String>,
+		warnings: Vec<ConfigIssue>,
+	},
+	Err(Vec<ConfigIssue>),
+}
+
+/// A problem with a config value, pointing at the offending key where possible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+	/// dotted path of the key
+	pub key: Option<String>,
+	/// where the value came from, e.g. `config.toml:12`, `VOICE_PORT` or `--port`
+	pub location: Option<String>,
+	pub message: String,
+}
+
+impl ConfigIssue {
+	fn new(key: &str, message: impl Into<String>) -> Self {
+		Self {
+			key: Some(key.to_string()),
+			location: None,
+			message: message.into(),
+		}
+	}
+}
+
+impl Display for ConfigIssue {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if let Some(location) = &self.location {
+			write!(f, "{location}: ")?;
+		}
+		if let Some(key) = &self.key {
+			write!(f, "`{key}`: ")?;
+		}
+		write!(f, "{}", self.message)
+	}
+}
+
+/// A config value set outside of the config file
+#[derive(Debug, Clone)]
+pub struct ConfigOverride {
+	/// where it came from, e.g. `VOICE_PORT` or `--port`
+	pub origin: String,
+	/// dotted path of the key
+	pub key: String,
+	pub value: toml::Value,
+	/// the unparsed value of an environment variable, used as a string if `value` has the wrong type
+	pub raw: Option<String>,
+}
+
+/// Where the config is loaded from
+#[derive(Debug)]
+pub struct ConfigSource {
+	pub path: PathBuf,
+	/// applied on top of the file and environment, e.g. command line flags
+	pub overrides: Vec<ConfigOverride>,
+}
+
+static CONFIG_SOURCE: OnceLock<ConfigSource> = OnceLock::new();
+
+/// Set where the config is loaded from. Must be called before the first [`reload_config`]
+pub fn set_config_source(source: ConfigSource) {
+	CONFIG_SOURCE.set(source).expect("config source is already set");
+}
+
+fn config_source() -> &'static ConfigSource {
+	CONFIG_SOURCE.get_or_init(|| ConfigSource {
+		path: PathBuf::from(DEFAULT_CONFIG_PATH),
+		overrides: Vec::new(),
+	})
+}
+
+/// `VOICE_RATE_LIMIT__ENABLED=false` overrides `rate_limit.enabled`.
+/// Values are parsed as toml, falling back to a plain string, also for string fields whose value looks like
+/// another type, e.g. `VOICE_ADMIN_TOKEN=1234`.
+fn env_overrides() -> Vec<ConfigOverride> {
+	let mut overrides = std::env::vars()
+		.filter_map(|(name, raw)| {
+			let key = name.strip_prefix(ENV_PREFIX)?;
+			// the config path itself, handled by the command line parser
+			if key == "CONFIG" {
+				return None;
+			}
+			let key = key.to_lowercase().replace("__", ".");
+			Some(ConfigOverride {
+				origin: name,
+				key,
+				value: parse_env_value(&raw),
+				raw: Some(raw),
+			})
+		})
+		.collect::<Vec<_>>();
+	overrides.sort_by(|a, b| a.origin.cmp(&b.origin));
+	overrides
+}
+
+fn parse_env_value(raw: &str) -> toml::Value {
+	toml::from_str::<toml::Table>(&format!("value = {raw}"))
+		.ok()
+		.and_then(|mut x| x.remove("value"))
+		.unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn set_key(table: &mut toml::Table, key: &str, value: toml::Value) {
+	match key.split_once('.') {
+		Some((head, rest)) => {
+			let entry = table.entry(head).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+			if !entry.is_table() {
+				*entry = toml::Value::Table(toml::Table::new());
+			}
+			set_key(entry.as_table_mut().unwrap(), rest, value);
+		}
+		None => {
+			table.insert(key.to_string(), value);
+		}
+	}
+}
+
+fn has_key(table: &toml::Table, key: &str) -> bool {
+	match key.split_once('.') {
+		Some((head, rest)) => table.get(head).and_then(|x| x.as_table()).is_some_and(|x| has_key(x, rest)),
+		None => table.contains_key(key),
+	}
+}
+
+/// The effective config, see [`load_config`]
+pub struct LoadedConfig {
+	pub config: Config,
+	pub warnings: Vec<ConfigIssue>,
+	file_exists: bool,
+	file_text: String,
+	overrides: Vec<ConfigOverride>,
+}
+
+impl LoadedConfig {
+	/// [`Config::validate`], with the issues pointed at where the keys were set
+	pub fn validate(&self) -> Vec<ConfigIssue> {
+		let mut errors = self.config.validate();
+		for issue in &mut errors {
+			self.locate(issue);
+		}
+		errors
+	}
+
+	/// Point issues at the override or file line that set the key
+	fn locate(&self, issue: &mut ConfigIssue) {
+		let path = &config_source().path;
+		let Some(key) = &issue.key else {
+			issue.location = Some(path.display().to_string());
+			return;
+		};
+		issue.location = match self.overrides.iter().rev().find(|x| x.key == *key) {
+			Some(x) => Some(x.origin.clone()),
+			None => match key_line(&self.file_text, key) {
+				Some(line) => Some(format!("{}:{line}", path.display())),
+				None => Some(path.display().to_string()),
+			},
+		};
+	}
+}
+
+/// Read the config file and apply `VOICE_*` environment variables and the overrides from [`ConfigSource`] on top.
+/// Doesn't validate or write anything.
+pub fn load_config() -> Result<LoadedConfig, Vec<ConfigIssue>> {
+	let source = config_source();
+
+	let io_error = |err: std::io::Error| {
+		vec![ConfigIssue {
+			key: None,
+			location: Some(source.path.display().to_string()),
+			message: err.to_string(),
+		}]
+	};
+
+	let file_exists = source.path.try_exists().map_err(io_error)?;
+	let file_text = if file_exists { std::fs::read_to_string(&source.path).map_err(io_error)? } else { String::new() };
+	let (mut config, mut warnings) = parse_config(&file_text, &source.path)?;
+
+	let overrides = env_overrides().into_iter().chain(source.overrides.iter().cloned()).collect::<Vec<_>>();
+	if !overrides.is_empty() {
+		config = apply_overrides(config, &overrides, &mut warnings)?;
+	}
+
+	Ok(LoadedConfig {
+		config,
+		warnings,
+		file_exists,
+		file_text,
+		overrides,
+	})
+}
+
+/// Apply `overrides` in order, unknown keys are skipped with a warning
+fn apply_overrides(
+	mut config: Config,
+	overrides: &[ConfigOverride],
+	warnings: &mut Vec<ConfigIssue>,
+) -> Result<Config, Vec<ConfigIssue>> {
+	let toml::Value::Table(mut table) = toml::Value::try_from(&config).expect("failed to serialize config to toml")
+	else {
+		unreachable!("config serializes to a table");
+	};
+
+	// apply one by one to blame the right override
+	let mut errors = Vec::new();
+	for x in overrides {
+		let apply = |value: toml::Value| {
+			let mut candidate = table.clone();
+			set_key(&mut candidate, &x.key, value);
+			toml::Value::Table(candidate.clone()).try_into::<Config>().map(|config| (candidate, config))
+		};
+		let mut result = apply(x.value.clone());
+		if let (Err(_), Some(raw)) = (&result, &x.raw) {
+			if !x.value.is_str() {
+				// e.g. an all digit token, which parses as an integer
+				if let Ok(applied) = apply(toml::Value::String(raw.clone())) {
+					result = Ok(applied);
+				}
+			}
+		}
+		match result {
+			Ok((candidate, new_config)) => {
+				let known = toml::Value::try_from(&new_config).ok();
+				if !known.as_ref().and_then(|x| x.as_table()).is_some_and(|known| has_key(known, &x.key)) {
+					warnings.push(ConfigIssue {
+						key: Some(x.key.clone()),
+						location: Some(x.origin.clone()),
+						message: "unknown key, ignored".to_string(),
+					});
+					continue;
+				}
+				table = candidate;
+				config = new_config;
+			}
+			Err(err) => errors.push(ConfigIssue {
+				key: Some(x.key.clone()),
+				location: Some(x.origin.clone()),
+				message: err.message().trim().to_string(),
+			}),
+		}
+	}
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	Ok(config)
+}
+
+/// Find the line on which the dotted `key` is assigned
+fn key_line(text: &str, key: &str) -> Option<usize> {
+	let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+	let mut current_table = String::new();
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if let Some(header) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+			current_table = header.trim().to_string();
+			if current_table == key {
+				return Some(i + 1);
+			}
+			continue;
+		}
+		let Some((lhs, _)) = line.split_once('=') else {
+			continue;
+		};
+		if current_table == table && lhs.trim().trim_matches('"') == name {
+			return Some(i + 1);
+		}
+		// tables written inline, e.g. `submit = { burst = 5, per_minute = 10 }`
+		if key.starts_with(&format!("{}.", lhs.trim())) && current_table.is_empty() {
+			return Some(i + 1);
+		}
+	}
+	None
+}
+
+/// Keys present in the file that don't correspond to any config field
+fn unknown_keys(prefix: &str, file: &toml::Table, known: &toml::Table, unknown: &mut Vec<String>) {
+	for (key, value) in file {
+		let path = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+		match (value, known.get(key)) {
+			(_, None) => unknown.push(path),
+			(toml::Value::Table(file), Some(toml::Value::Table(known))) => unknown_keys(&path, file, known, unknown),
+			_ => {}
+		}
+	}
+}
+
+/// Parse the config file, reporting the exact location of syntax and type errors
+fn parse_config(text: &str, path: &Path) -> Result<(Config, Vec<ConfigIssue>), Vec<ConfigIssue>> {
+	let location = |line: Option<usize>| match line {
+		Some(line) => format!("{}:{line}", path.display()),
+		None => path.display().to_string(),
+	};
+
+	let config: Config = toml::from_str(text).map_err(|err| {
+		let line = err.span().map(|span| text[..span.start].matches('\n').count() + 1);
+		// the span can point at the value, the key name is on the same line
+		let key = line
+			.and_then(|line| text.lines().nth(line - 1))
+			.and_then(|x| x.split_once('='))
+			.map(|(key, _)| key.trim().to_string());
+		vec![ConfigIssue {
+			key,
+			location: Some(location(line)),
+			message: err.message().trim().to_string(),
+		}]
+	})?;
+
+	let mut unknown = Vec::new();
+	if let (Ok(file), Ok(toml::Value::Table(known))) = (toml::from_str::<toml::Table>(text), toml::Value::try_from(&config))
+	{
+		unknown_keys("", &file, &known, &mut unknown);
+	}
+	let warnings = unknown
+		.into_iter()
+		.map(|key| ConfigIssue {
+			location: Some(location(key_line(text, &key))),
+			key: Some(key),
+			message: "unknown key, ignored".to_string(),
+		})
+		.collect();
+
+	Ok((config, warnings))
+}
+
+/// Whether a changed field, as reported by [`reload_config`], only takes effect after a restart
+pub fn requires_restart(field: &str) -> bool {
+	RESTART_REQUIRED_FIELDS
+		.iter()
+		.any(|x| field == *x || field.strip_prefix(x).is_some_and(|rest| rest.starts_with('.')))
+}
+
+/// Collects the dotted paths of values that differ between `old` and `new`
+fn changed_fields(prefix: &str, old: &serde_json::Value, new: &serde_json::Value, changed: &mut Vec<String>) {
+	use serde_json::Value;
+
+	match (old, new) {
+		(Value::Object(old), Value::Object(new)) => {
+			for key in old.keys().chain(new.keys().filter(|x| !old.contains_key(*x))) {
+				let path = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+				changed_fields(&path, old.get(key).unwrap_or(&Value::Null), new.get(key).unwrap_or(&Value::Null), changed);
+			}
+		}
+		(old, new) if old != new => changed.push(prefix.to_string()),
+		_ => {}
+	}
 }
 
 /// Wrapper for [`tracing::Level`] which supports serde
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
 pub enum LogLevel {
+	#[serde(alias = "trace")]
 	Trace,
+	#[serde(alias = "debug")]
 	Debug,
+	#[serde(alias = "info")]
 	Info,
+	#[serde(alias = "warn")]
 	Warn,
+	#[serde(alias = "error")]
 	Error,
 }
 
@@ -38,7 +409,412 @@ impl From<LogLevel> for tracing::Level {
 	}
 }
 
+/// Where the server listens.
+///
+/// Written as an ip address (`0.0.0.0`, `::`) which is combined with [`Config::port`],
+/// or as `unix:<path>` for a unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BindAddress {
+	Ip(IpAddr),
+	Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if let Some(path) = s.strip_prefix("unix:") {
+			if path.is_empty() {
+				return Err("unix socket path is empty".to_string());
+			}
+			return Ok(Self::Unix(PathBuf::from(path)));
+		}
+		// tolerate the bracketed ipv6 form
+		let ip = s.trim_start_matches('[').trim_end_matches(']');
+		ip.parse().map(Self::Ip).map_err(|_| format!("\"{s}\" is neither an ip address nor unix:<path>"))
+	}
+}
+
+impl TryFrom<String> for BindAddress {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<BindAddress> for String {
+	fn from(value: BindAddress) -> Self {
+		value.to_string()
+	}
+}
+
+impl Display for BindAddress {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Ip(ip) => write!(f, "{ip}"),
+			Self::Unix(path) => write!(f, "unix:{}", path.display()),
+		}
+	}
+}
+
+impl Default for BindAddress {
+	fn default() -> Self {
+		Self::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+	}
+}
+
+/// Token bucket parameters for one group of endpoints
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct RateLimit {
+	/// max number of requests a client can make in a burst
+	pub burst: u32,
+	/// rate at which a client's allowance refills
+	pub per_minute: u32,
+}
+
+impl RateLimit {
+	const SUBMIT: Self = Self {
+		burst: 5,
+		per_minute: 10,
+	};
+	const STATUS: Self = Self {
+		burst: 30,
+		per_minute: 120,
+	};
+	const WEBSOCKET: Self = Self {
+		burst: 10,
+		per_minute: 30,
+	};
+
+	pub fn per_second(&self) -> f64 {
+		self.per_minute as f64 / 60.0
+	}
+}
+
+/// Read a [`RateLimit`] table, keys missing from it keep their value from `default`
+fn partial_rate_limit<'de, D: serde::Deserializer<'de>>(
+	deserializer: D,
+	default: RateLimit,
+) -> Result<RateLimit, D::Error> {
+	#[derive(serde::Deserialize)]
+	struct PartialRateLimit {
+		burst: Option<u32>,
+		per_minute: Option<u32>,
+	}
+	let partial = <PartialRateLimit as serde::Deserialize>::deserialize(deserializer)?;
+	Ok(RateLimit {
+		burst: partial.burst.unwrap_or(default.burst),
+		per_minute: partial.per_minute.unwrap_or(default.per_minute),
+	})
+}
+
+fn submit_rate_limit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<RateLimit, D::Error> {
+	partial_rate_limit(deserializer, RateLimit::SUBMIT)
+}
+
+fn status_rate_limit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<RateLimit, D::Error> {
+	partial_rate_limit(deserializer, RateLimit::STATUS)
+}
+
+fn websocket_rate_limit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<RateLimit, D::Error> {
+	partial_rate_limit(deserializer, RateLimit::WEBSOCKET)
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+	/// disable to let every request through
+	pub enabled: bool,
+	/// header set by a reverse proxy that contains the client ip, e.g. `X-Forwarded-For`.
+	/// only set this if the server is not reachable directly
+	pub trusted_proxy_header: Option<String>,
+	/// limit for `/submit`
+	#[serde(deserialize_with = "submit_rate_limit")]
+	pub submit: RateLimit,
+	/// limit for `/status`
+	#[serde(deserialize_with = "status_rate_limit")]
+	pub status: RateLimit,
+	/// limit for opening `/status_ws`, `/tasks_ws` and `/status_sse` streams
+	#[serde(deserialize_with = "websocket_rate_limit")]
+	pub websocket: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			trusted_proxy_header: None,
+			submit: RateLimit::SUBMIT,
+			status: RateLimit::STATUS,
+			websocket: RateLimit::WEBSOCKET,
+		}
+	}
+}
+
+/// Automatic certificate management.
+///
+/// Requires `tls` and `http_redirect_port`, since the HTTP-01 challenge
+/// is answered on the plain http listener. The certificate authority always connects on port 80,
+/// so anything else has to be forwarded to it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AcmeConfig {
+	pub enabled: bool,
+	/// acme directory of the certificate authority
+	pub directory_url: String,
+	/// domains to put on the certificate
+	pub domains: Vec<String>,
+	/// email the certificate authority may use to contact about the account
+	pub contact_email: Option<String>,
+	/// account key, created on first use
+	pub account_key_path: PathBuf,
+	/// renew once the certificate is valid for less than this many days
+	pub renew_days_before_expiry: u32,
+	/// extra root certificate to trust when talking to the directory,
+	/// e.g. the one of a local test server
+	pub directory_ca_pem_path: Option<PathBuf>,
+}
+
+impl Default for AcmeConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			directory_url: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+			domains: Vec::new(),
+			contact_email: None,
+			account_key_path: PathBuf::from("./certificates/acme_account.pk8"),
+			renew_days_before_expiry: 30,
+			directory_ca_pem_path: None,
+		}
+	}
+}
+
+/// Hot folder: files dropped into `input_dir` are processed like uploads
+/// and the results are written to `output_dir` under their original names
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+	pub enabled: bool,
+	pub input_dir: PathBuf,
+	pub output_dir: PathBuf,
+	/// how often to scan `input_dir`. a file is picked up once its size
+	/// and modification time haven't changed between two scans
+	pub poll_interval_seconds: u64,
+}
+
+impl Default for WatchConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			input_dir: PathBuf::from("./watch"),
+			output_dir: PathBuf::from("./watch_results"),
+			poll_interval_seconds: 5,
+		}
+	}
+}
+
+/// Status websockets
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WebsocketConfig {
+	/// tasks a single `/tasks_ws` socket may watch at once
+	pub max_subscriptions: usize,
+	pub ping_interval_seconds: u64,
+	/// close sockets which haven't sent anything, including pongs, for this long
+	pub idle_timeout_seconds: u64,
+}
+
+impl Default for WebsocketConfig {
+	fn default() -> Self {
+		Self {
+			max_subscriptions: 100,
+			ping_interval_seconds: 20,
+			idle_timeout_seconds: 60,
+		}
+	}
+}
+
+/// Where inputs and finished outputs are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+	/// in `inputs_dir` and `outputs_dir`
+	#[default]
+	Local,
+	/// uploaded to an s3 compatible bucket under `inputs/` and `outputs/`, see `storage.s3`
+	S3,
+}
+
+/// How `/videos/:video` serves outputs from a bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum S3ServeMode {
+	/// stream the object through this server
+	#[default]
+	Proxy,
+	/// redirect to a presigned url
+	Redirect,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+	/// e.g. `https://s3.eu-central-1.amazonaws.com`, or `http://localhost:9000` for MinIO
+	pub endpoint: String,
+	pub region: String,
+	pub bucket: String,
+	pub access_key_id: String,
+	pub secret_access_key: String,
+	/// prepended to object keys
+	pub prefix: String,
+	/// address objects as `endpoint/bucket/key` instead of `bucket.endpoint/key`. MinIO needs this
+	pub path_style: bool,
+	pub serve: S3ServeMode,
+	/// how long redirect urls stay valid
+	pub presign_expiry_seconds: u64,
+}
+
+impl Default for S3Config {
+	fn default() -> Self {
+		Self {
+			endpoint: String::new(),
+			region: "us-east-1".to_string(),
+			bucket: String::new(),
+			access_key_id: String::new(),
+			secret_access_key: String::new(),
+			prefix: String::new(),
+			path_style: true,
+			serve: S3ServeMode::default(),
+			presign_expiry_seconds: 60 * 60,
+		}
+	}
+}
+
+/// Limits on the space used by task files, and where outputs are kept
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+	/// bytes that must stay free on the inputs and outputs filesystems
+	pub min_free_space: u64,
+	/// cap on the bytes in `inputs_dir` and `outputs_dir` together, unlimited if unset
+	pub max_total_size: Option<u64>,
+	/// inputs and outputs are in `inputs_dir` and `outputs_dir` while tasks run, ffmpeg works on local files
+	pub backend: StorageBackend,
+	pub s3: S3Config,
+}
+
+impl Default for StorageConfig {
+	fn default() -> Self {
+		Self {
+			min_free_space: 256 * 1024 * 1024,
+			max_total_size: None,
+			backend: StorageBackend::default(),
+			s3: S3Config::default(),
+		}
+	}
+}
+
+/// How long task files are kept, besides completed outputs which follow `delete_files_after_minutes`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+	/// inputs are deleted this long after their task finishes, they aren't needed after encoding
+	pub inputs_minutes: u64,
+	/// failed and cancelled tasks are removed after this long
+	pub failed_minutes: u64,
+	/// upper limit for `keep_hours` requested at submit
+	pub max_keep_hours: u64,
+	/// longest time between cleanups, they also run when a task finishes or expires
+	pub sweep_interval_seconds: u64,
+}
+
+impl Default for RetentionConfig {
+	fn default() -> Self {
+		Self {
+			inputs_minutes: 0,
+			failed_minutes: 60,
+			max_keep_hours: 24 * 7,
+			sweep_interval_seconds: 60,
+		}
+	}
+}
+
+/// What happens on SIGTERM and SIGINT, or while draining with `/admin/drain`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+	/// on shutdown, wait this long for running tasks to finish before cancelling them. 0 to cancel right away
+	pub task_timeout_seconds: u64,
+	/// then give open requests, e.g. uploads and downloads, this long before closing their connections.
+	/// pending webhook deliveries get as long again once tasks are stopped
+	pub grace_period_seconds: u64,
+	/// completed tasks are saved here on shutdown and restored on startup, so their outputs stay available.
+	/// nothing is saved if unset, the default
+	pub state_file: Option<PathBuf>,
+}
+
+impl Default for ShutdownConfig {
+	fn default() -> Self {
+		Self {
+			task_timeout_seconds: 0,
+			grace_period_seconds: 10,
+			state_file: None,
+		}
+	}
+}
+
+/// Thresholds for `/readyz`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+	/// bytes that must be free on the inputs and outputs filesystems
+	pub min_free_space: u64,
+	/// not ready while this many tasks are unfinished
+	pub max_tasks_in_progress: usize,
+}
+
+impl Default for ReadinessConfig {
+	fn default() -> Self {
+		Self {
+			min_free_space: 1024 * 1024 * 1024,
+			max_tasks_in_progress: 16,
+		}
+	}
+}
+
+/// Completion callbacks, requested with `/submit?callback=<url>`
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+	/// callback urls must be one of these or below its path, e.g. `https://backend.example.com/hooks/`.
+	/// callbacks are rejected if empty
+	pub allowed_urls: Vec<String>,
+	/// key for the `X-Voice-Signature` hmac. required if `allowed_urls` is set
+	pub secret: Option<String>,
+	/// give up after this many failed deliveries
+	pub max_attempts: u32,
+	/// wait this long before the first retry, doubling every time
+	pub initial_backoff_seconds: u64,
+	pub timeout_seconds: u64,
+}
+
+impl Default for WebhookConfig {
+	fn default() -> Self {
+		Self {
+			allowed_urls: Vec::new(),
+			secret: None,
+			max_attempts: 5,
+			initial_backoff_seconds: 5,
+			timeout_seconds: 10,
+		}
+	}
+}
+
+/// Missing fields are taken from [`Config::default`]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct Config {
 	/// level to log at
 	pub log_level: LogLevel,
@@ -56,12 +832,47 @@ pub struct Config {
 	pub max_file_size: u64,
 	/// port to bind to
 	pub port: u16,
-	/// delete input/output files after this many minutes
+	/// address to bind to, see [`BindAddress`]
+	pub bind_address: BindAddress,
+	/// serve https using `cert_pem_path` and `key_pem_path`.
+	/// disable to serve plain http, e.g. behind a tls terminating reverse proxy
+	pub tls: bool,
+	/// if set and tls is enabled, also listen for plain http on this port
+	/// and redirect every request to https
+	pub http_redirect_port: Option<u16>,
+	/// remove completed tasks and their outputs after this many minutes, unless the submitter asked
+	/// to keep them longer. see `retention` for everything else
 	pub delete_files_after_minutes: u64,
 	/// certificate path
 	pub cert_pem_path: PathBuf,
 	/// key path
 	pub key_pem_path: PathBuf,
+	/// check the certificate and key for changes this often and reload them. 0 to only reload on SIGHUP
+	pub tls_reload_interval_seconds: u64,
+	/// per-client request rate limits
+	pub rate_limit: RateLimitConfig,
+	/// certificate management
+	pub acme: AcmeConfig,
+	/// bearer token for the `/admin` endpoints. they are disabled if unset
+	pub admin_token: Option<String>,
+	/// bearer token for `/metrics`. it's public if unset
+	pub metrics_token: Option<String>,
+	/// how often a task may publish its progress to status clients, from 0.01 to 100
+	pub progress_updates_per_second: f32,
+	/// watch folder ingestion
+	pub watch: WatchConfig,
+	/// completion callbacks
+	pub webhook: WebhookConfig,
+	/// status websockets
+	pub websocket: WebsocketConfig,
+	/// `/readyz` checks
+	pub readiness: ReadinessConfig,
+	/// free space and quota, oldest completed tasks without `keep_hours` are evicted to stay within them
+	pub storage: StorageConfig,
+	/// cleanup of inputs and failed tasks
+	pub retention: RetentionConfig,
+	/// graceful shutdown and draining
+	pub shutdown: ShutdownConfig,
 }
 
 impl Default for Config {
@@ -78,9 +889,25 @@ impl Default for Config {
 			ffmpeg_executable: PathBuf::from("ffmpeg"),
 			max_file_size: 1024 * 1024 * 1024, // 1 GiB
 			port: 443,
+			bind_address: BindAddress::default(),
+			tls: true,
+			http_redirect_port: None,
 			delete_files_after_minutes: 60,
 			cert_pem_path: PathBuf::from("./certificates/cert.pem"),
 			key_pem_path: PathBuf::from("./certificates/key.pem"),
+			tls_reload_interval_seconds: 60,
+			rate_limit: RateLimitConfig::default(),
+			acme: AcmeConfig::default(),
+			admin_token: None,
+			metrics_token: None,
+			progress_updates_per_second: 4.0,
+			watch: WatchConfig::default(),
+			webhook: WebhookConfig::default(),
+			websocket: WebsocketConfig::default(),
+			readiness: ReadinessConfig::default(),
+			storage: StorageConfig::default(),
+			retention: RetentionConfig::default(),
+			shutdown: ShutdownConfig::default(),
 		}
 	}
 }
@@ -90,6 +917,10 @@ impl Config {
 		which::which(&self.ffmpeg_executable).is_ok()
 	}
 
+	pub fn progress_interval(&self) -> Duration {
+		Duration::from_secs_f32(1.0 / self.progress_updates_per_second)
+	}
+
 	pub fn web_dir_found(&self) -> bool {
 		if !self.web_root.exists() {
 			return false;
@@ -97,10 +928,10 @@ impl Config {
 
 		let mut index = false;
 		let mut completed = false;
-		for file in self
-			.web_root
-			.read_dir()
-			.unwrap()
+		let Ok(entries) = self.web_root.read_dir() else {
+			return false;
+		};
+		for file in entries
 			.flatten()
 			.map(|x| x.file_name().to_string_lossy().to_string())
 		{
@@ -115,13 +946,232 @@ impl Config {
 		index && completed
 	}
 
-	pub fn init_directories(&self) -> std::io::Result<()> {
-		std::fs::create_dir_all(&self.inputs_dir)?;
-		std::fs::create_dir_all(&self.outputs_dir)?;
-		std::fs::create_dir_all(&self.log_file_root)
+	/// Create the data directories that don't exist yet. [`Config::validate`] only checks that they can be
+	pub fn create_dirs(&self) -> Vec<ConfigIssue> {
+		let mut dirs = vec![
+			("inputs_dir", self.inputs_dir.as_path()),
+			("outputs_dir", self.outputs_dir.as_path()),
+			("log_file_root", self.log_file_root.as_path()),
+		];
+		if self.watch.enabled {
+			dirs.extend([("watch.input_dir", self.watch.input_dir.as_path()), ("watch.output_dir", &self.watch.output_dir)]);
+		}
+		if let Some(dir) = self.shutdown.state_file.as_deref().and_then(Path::parent) {
+			dirs.push(("shutdown.state_file", dir));
+		}
+
+		let mut issues = Vec::new();
+		for (key, dir) in dirs {
+			if let Err(err) = std::fs::create_dir_all(dir) {
+				issues.push(ConfigIssue::new(key, format!("failed to create \"{}\": {err}", dir.display())));
+			}
+		}
+		issues
+	}
+
+	/// Checks every value, collecting all problems instead of stopping at the first one.
+	/// Doesn't change anything on disk
+	pub fn validate(&self) -> Vec<ConfigIssue> {
+		let mut issues = Vec::new();
+
+		if self.port == 0 {
+			issues.push(ConfigIssue::new("port", "must not be 0"));
+		}
+		if let Some(redirect_port) = self.http_redirect_port {
+			if redirect_port == 0 {
+				issues.push(ConfigIssue::new("http_redirect_port", "must not be 0"));
+			}
+			if redirect_port == self.port {
+				issues.push(ConfigIssue::new("http_redirect_port", "must differ from `port`"));
+			}
+		}
+		if self.tls && matches!(self.bind_address, BindAddress::Unix(_)) {
+			issues.push(ConfigIssue::new(
+				"tls",
+				"tls is not supported on unix sockets. set tls = false and terminate tls in the proxy",
+			));
+		}
+
+		for (key, dir) in
+			[("inputs_dir", &self.inputs_dir), ("outputs_dir", &self.outputs_dir), ("log_file_root", &self.log_file_root)]
+		{
+			if let Err(err) = check_dir_writable(dir) {
+				issues.push(ConfigIssue::new(key, format!("\"{}\" is not writable: {err}", dir.display())));
+			}
+		}
+
+		if !self.encoder_found() {
+			issues.push(ConfigIssue::new(
+				"ffmpeg_executable",
+				format!("encoder not found. specified path: \"{}\"", self.ffmpeg_executable.display()),
+			));
+		}
+		if !self.web_dir_found() {
+			issues.push(ConfigIssue::new(
+				"web_root",
+				format!("\"{}\" must contain index.html and completed.html", self.web_root.display()),
+			));
+		}
+
+		// acme creates the files itself
+		if self.tls && !self.acme.enabled {
+			for (key, path) in [("cert_pem_path", &self.cert_pem_path), ("key_pem_path", &self.key_pem_path)] {
+				match std::fs::read(path) {
+					Ok(data) if data.is_empty() => {
+						issues.push(ConfigIssue::new(key, format!("\"{}\" is empty", path.display())))
+					}
+					Ok(_) => {}
+					Err(err) => {
+						issues.push(ConfigIssue::new(key, format!("can't read \"{}\": {err}", path.display())))
+					}
+				}
+			}
+		}
+
+		if self.acme.enabled {
+			if !self.tls {
+				issues.push(ConfigIssue::new("acme.enabled", "acme requires tls"));
+			}
+			if self.http_redirect_port.is_none() {
+				issues.push(ConfigIssue::new("acme.enabled", "acme requires http_redirect_port for the http-01 challenge"));
+			}
+			if self.acme.domains.is_empty() {
+				issues.push(ConfigIssue::new("acme.domains", "acme is enabled, but no domains are configured"));
+			}
+		}
+
+		if self.max_file_size == 0 {
+			issues.push(ConfigIssue::new("max_file_size", "must be greater than 0"));
+		}
+		if self.delete_files_after_minutes == 0 {
+			issues.push(ConfigIssue::new("delete_files_after_minutes", "must be greater than 0"));
+		}
+		if self.rate_limit.enabled {
+			for (key, limit) in [
+				("rate_limit.submit.burst", self.rate_limit.submit),
+				("rate_limit.status.burst", self.rate_limit.status),
+				("rate_limit.websocket.burst", self.rate_limit.websocket),
+			] {
+				if limit.burst == 0 {
+					issues.push(ConfigIssue::new(key, "must be at least 1, otherwise every request is rejected"));
+				}
+			}
+		}
+		if self.admin_token.as_ref().is_some_and(|x| x.len() < 16) {
+			issues.push(ConfigIssue::new("admin_token", "must be at least 16 characters long"));
+		}
+		if self.metrics_token.as_ref().is_some_and(|x| x.len() < 16) {
+			issues.push(ConfigIssue::new("metrics_token", "must be at least 16 characters long"));
+		}
+
+		if self.watch.enabled {
+			for (key, dir) in [("watch.input_dir", &self.watch.input_dir), ("watch.output_dir", &self.watch.output_dir)] {
+				if let Err(err) = check_dir_writable(dir) {
+					issues.push(ConfigIssue::new(key, format!("\"{}\" is not writable: {err}", dir.display())));
+				}
+			}
+			if self.watch.poll_interval_seconds == 0 {
+				issues.push(ConfigIssue::new("watch.poll_interval_seconds", "must be greater than 0"));
+			}
+		}
+
+		// also keeps `progress_interval` from panicking, NaN isn't in the range either
+		if !(0.01..=100.0).contains(&self.progress_updates_per_second) {
+			issues.push(ConfigIssue::new("progress_updates_per_second", "must be between 0.01 and 100"));
+		}
+		if self.websocket.max_subscriptions == 0 {
+			issues.push(ConfigIssue::new("websocket.max_subscriptions", "must be at least 1"));
+		}
+		if self.websocket.ping_interval_seconds == 0 {
+			issues.push(ConfigIssue::new("websocket.ping_interval_seconds", "must be greater than 0"));
+		}
+		if self.websocket.idle_timeout_seconds <= self.websocket.ping_interval_seconds {
+			issues.push(ConfigIssue::new(
+				"websocket.idle_timeout_seconds",
+				"must be greater than ping_interval_seconds, otherwise clients have no time to answer pings",
+			));
+		}
+
+		if self.storage.max_total_size.is_some_and(|x| x < self.max_file_size) {
+			issues.push(ConfigIssue::new("storage.max_total_size", "must be at least max_file_size"));
+		}
+		if self.retention.sweep_interval_seconds == 0 {
+			issues.push(ConfigIssue::new("retention.sweep_interval_seconds", "must be greater than 0"));
+		}
+		if let Some(dir) = self.shutdown.state_file.as_deref().and_then(Path::parent) {
+			// a bare file name has an empty parent
+			let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+			if let Err(err) = check_dir_writable(dir) {
+				issues.push(ConfigIssue::new("shutdown.state_file", format!("\"{}\" is not writable: {err}", dir.display())));
+			}
+		}
+		if self.readiness.max_tasks_in_progress == 0 {
+			issues.push(ConfigIssue::new("readiness.max_tasks_in_progress", "must be at least 1"));
+		}
+
+		if self.storage.backend == StorageBackend::S3 {
+			let s3 = &self.storage.s3;
+			match reqwest::Url::parse(&s3.endpoint) {
+				Ok(url) if url.host_str().is_none() => {
+					issues.push(ConfigIssue::new("storage.s3.endpoint", "must include a host"))
+				}
+				Ok(_) => {}
+				Err(err) => issues.push(ConfigIssue::new("storage.s3.endpoint", format!("not a valid url: {err}"))),
+			}
+			for (key, value) in [
+				("storage.s3.region", &s3.region),
+				("storage.s3.bucket", &s3.bucket),
+				("storage.s3.access_key_id", &s3.access_key_id),
+				("storage.s3.secret_access_key", &s3.secret_access_key),
+			] {
+				if value.is_empty() {
+					issues.push(ConfigIssue::new(key, "required for the s3 backend"));
+				}
+			}
+			// the longest signature v4 allows
+			if !(1..=7 * 24 * 60 * 60).contains(&s3.presign_expiry_seconds) {
+				issues.push(ConfigIssue::new("storage.s3.presign_expiry_seconds", "must be between 1 and 604800"));
+			}
+		}
+
+		if !self.webhook.allowed_urls.is_empty() {
+			match &self.webhook.secret {
+				None => issues.push(ConfigIssue::new("webhook.secret", "required to sign callbacks")),
+				Some(x) if x.len() < 16 => {
+					issues.push(ConfigIssue::new("webhook.secret", "must be at least 16 characters long"))
+				}
+				Some(_) => {}
+			}
+			for url in &self.webhook.allowed_urls {
+				if let Err(err) = reqwest::Url::parse(url) {
+					issues.push(ConfigIssue::new("webhook.allowed_urls", format!("\"{url}\" is not a valid url: {err}")));
+				}
+			}
+			if self.webhook.max_attempts == 0 {
+				issues.push(ConfigIssue::new("webhook.max_attempts", "must be at least 1"));
+			}
+		}
+
+		issues
 	}
 }
 
+/// Check that files can be created in `dir`, or that `dir` can be created if it doesn't exist yet
+pub fn check_dir_writable(dir: &Path) -> std::io::Result<()> {
+	// the closest ancestor that exists, a bare relative path has an empty one
+	let mut existing = dir;
+	while !existing.as_os_str().is_empty() && !existing.exists() {
+		existing = existing.parent().unwrap_or(Path::new(""));
+	}
+	if existing.as_os_str().is_empty() {
+		existing = Path::new(".");
+	}
+	if !existing.is_dir() {
+		return Err(std::io::Error::other(format!("\"{}\" is not a directory", existing.display())));
+	}
+	crate::disk::check_writable(existing)
+}
+
 pub struct ConfigStatic(OnceLock<RwLock<Config>>);
 
 impl ConfigStatic {
@@ -135,44 +1185,177 @@ pub static CONFIG: ConfigStatic = ConfigStatic(OnceLock::new());
 
 /// Reloads the app config.
 ///
-/// Loads the config form the file or writes to the file, if one was created by calling this function.
+/// Loads the config from the file, see [`load_config`], or writes the defaults to the file if there is none.
 /// Initializes the app config if it has not been initialized yet.
-/// No-op if the app config has not been initialized yet.
+/// The new config is validated first and only replaces the current one if there are no errors.
 pub async fn reload_config() -> ConfigReloadResult {
-	let config_file_path = Path::new(CONFIG_PATH);
-
 	let mut current_config_lock = CONFIG.0.get_or_init(|| RwLock::new(Config::default())).write().await;
 
-	// Read the config file if it exists.
-	// Returns on io or parse error, otherwise evaluates to [`Option<Config>`],
-	// depending on whether config file exists
-	let config_read_option: Option<Config> = match config_file_path.try_exists() {
-		Ok(false) => None,
-		Ok(true) => {
-			let Ok(file_data) = std::fs::read_to_string(config_file_path).map_err(|_| ()) else {
-				return ConfigReloadResult::Err;
-			};
-			toml::from_str(&file_data).ok()
+	let LoadedConfig {
+		config: new_config,
+		warnings,
+		..
+	} = match load_config() {
+		Ok(mut loaded) => {
+			let errors = loaded.validate();
+			if !errors.is_empty() {
+				return ConfigReloadResult::Err(errors);
+			}
+			let errors = loaded.config.create_dirs();
+			if !errors.is_empty() {
+				return ConfigReloadResult::Err(errors);
+			}
+
+			// Config file does not exist => write the defaults to it
+			if !loaded.file_exists {
+				let config_string = toml::to_string(&Config::default()).expect("failed to serialize config to toml");
+				// e.g. a read-only working directory, the defaults and overrides still apply
+				if let Err(err) = std::fs::write(&config_source().path, config_string) {
+					let mut issue = ConfigIssue {
+						key: None,
+						location: None,
+						message: format!("failed to write default config: {err}"),
+					};
+					loaded.locate(&mut issue);
+					loaded.warnings.push(issue);
+				}
+			}
+			loaded
 		}
-		Err(_) => return ConfigReloadResult::Err,
+		Err(errors) => return ConfigReloadResult::Err(errors),
 	};
 
-	match config_read_option {
-		// Config file exists => replace current config with the loaded one
-		Some(new_config) => {
-			*current_config_lock = new_config;
-		}
-		// Config file does not exist => write current config to file
-		None => {
-			let config_string = toml::to_string(&*current_config_lock).expect("failed to serialize config to toml");
-			// this should never fail, because we already made some syscalls to check if the file exists
-			std::fs::write(config_file_path, config_string).expect("failed to write config file");
+	let mut changed = Vec::new();
+	changed_fields(
+		"",
+		&serde_json::to_value(&*current_config_lock).unwrap(),
+		&serde_json::to_value(&new_config).unwrap(),
+		&mut changed,
+	);
+	*current_config_lock = new_config;
+
+	ConfigReloadResult::Ok { changed, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(text: &str) -> Result<(Config, Vec<ConfigIssue>), Vec<ConfigIssue>> {
+		parse_config(text, Path::new("config.toml"))
+	}
+
+	#[test]
+	fn duplicate_key_location() {
+		let errors = parse("port = 1\nadmin_token = \"x\"\nport = 2\n").unwrap_err();
+		assert_eq!(errors[0].location.as_deref(), Some("config.toml:3"));
+		assert_eq!(errors[0].key.as_deref(), Some("port"));
+	}
+
+	#[test]
+	fn type_error_location() {
+		let errors = parse("tls = false\n\n[rate_limit]\nenabled = \"yes\"\n").unwrap_err();
+		assert_eq!(errors[0].location.as_deref(), Some("config.toml:4"));
+		assert_eq!(errors[0].key.as_deref(), Some("enabled"));
+	}
+
+	#[test]
+	fn partial_rate_limit_table() {
+		let (config, warnings) = parse("[rate_limit.submit]\nburst = 2\n\n[rate_limit.status]\n").unwrap();
+		assert!(warnings.is_empty());
+		assert_eq!(config.rate_limit.submit.burst, 2);
+		assert_eq!(config.rate_limit.submit.per_minute, RateLimit::SUBMIT.per_minute);
+		assert_eq!(config.rate_limit.status.burst, RateLimit::STATUS.burst);
+		assert_eq!(config.rate_limit.websocket.per_minute, RateLimit::WEBSOCKET.per_minute);
+	}
+
+	fn env_override(key: &str, raw: &str) -> ConfigOverride {
+		ConfigOverride {
+			origin: format!("VOICE_{}", key.to_uppercase().replace('.', "__")),
+			key: key.to_string(),
+			value: parse_env_value(raw),
+			raw: Some(raw.to_string()),
 		}
 	}
 
-	current_config_lock
-		.init_directories()
-		.expect("failed to create necessary directories");
+	#[test]
+	fn numeric_string_override() {
+		let overrides = [env_override("admin_token", "12345678901234567890"), env_override("port", "8080")];
+		let config = apply_overrides(Config::default(), &overrides, &mut Vec::new()).unwrap();
+		assert_eq!(config.admin_token.as_deref(), Some("12345678901234567890"));
+		assert_eq!(config.port, 8080);
+	}
+
+	#[test]
+	fn mistyped_override_is_blamed() {
+		let overrides = [env_override("rate_limit.submit.burst", "many")];
+		let errors = apply_overrides(Config::default(), &overrides, &mut Vec::new()).unwrap_err();
+		assert_eq!(errors[0].location.as_deref(), Some("VOICE_RATE_LIMIT__SUBMIT__BURST"));
+	}
+
+	#[test]
+	fn unknown_override_is_a_warning() {
+		let mut warnings = Vec::new();
+		apply_overrides(Config::default(), &[env_override("prot", "1")], &mut warnings).unwrap();
+		assert_eq!(warnings[0].key.as_deref(), Some("prot"));
+	}
+
+	#[test]
+	fn unknown_keys_are_warnings() {
+		let (_, warnings) = parse("port = 8080\n[rate_limit.submit]\nburts = 2\n").unwrap();
+		assert_eq!(warnings.len(), 1);
+		assert_eq!(warnings[0].key.as_deref(), Some("rate_limit.submit.burts"));
+		assert_eq!(warnings[0].location.as_deref(), Some("config.toml:3"));
+	}
+
+	#[test]
+	fn validate_doesnt_create_dirs() {
+		let root = std::env::temp_dir().join(format!("voice-config-{}", std::process::id()));
+		let config = Config {
+			inputs_dir: root.join("inputs"),
+			outputs_dir: root.join("outputs"),
+			log_file_root: root.join("logs"),
+			..Default::default()
+		};
+		let issues = config.validate();
+		assert!(!issues.iter().any(|x| x.key.as_deref() == Some("inputs_dir")));
+		assert!(!root.exists());
+
+		assert!(config.create_dirs().is_empty());
+		assert!(root.join("inputs").is_dir() && root.join("logs").is_dir());
+		std::fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn dir_below_a_file_isnt_writable() {
+		let file = std::env::temp_dir().join(format!("voice-config-file-{}", std::process::id()));
+		std::fs::write(&file, "").unwrap();
+		assert!(check_dir_writable(&file.join("inputs")).is_err());
+		assert!(check_dir_writable(Path::new("inputs")).is_ok());
+		std::fs::remove_file(file).unwrap();
+	}
 
-	ConfigReloadResult::Ok
+	#[test]
+	fn progress_updates_per_second_bounds() {
+		let cases = [
+			(4.0, true),
+			(0.01, true),
+			(100.0, true),
+			(0.0, false),
+			(1e-30, false),
+			(f32::INFINITY, false),
+			(f32::NAN, false),
+		];
+		for (value, valid) in cases {
+			let config = Config {
+				progress_updates_per_second: value,
+				..Default::default()
+			};
+			let rejected = config.validate().iter().any(|x| x.key.as_deref() == Some("progress_updates_per_second"));
+			assert_eq!(rejected, !valid, "{value}");
+			if valid {
+				config.progress_interval();
+			}
+		}
+	}
 }
@@ -0,0 +1,41 @@
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+/// total size of the files directly in `dir`
+pub async fn dir_size(dir: &Path) -> u64 {
+	let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+		return 0;
+	};
+	let mut size = 0;
+	while let Ok(Some(entry)) = entries.next_entry().await {
+		if let Ok(metadata) = entry.metadata().await {
+			if metadata.is_file() {
+				size += metadata.len();
+			}
+		}
+	}
+	size
+}
+
+/// Fails unless the current user may create files in the directory `path`
+pub fn check_writable(path: &Path) -> io::Result<()> {
+	let path = CString::new(path.as_os_str().as_bytes()).map_err(|x| io::Error::new(io::ErrorKind::InvalidInput, x))?;
+	// SAFETY: `path` is nul terminated
+	if unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `path`
+pub fn free_space(path: &Path) -> io::Result<u64> {
+	let path = CString::new(path.as_os_str().as_bytes()).map_err(|x| io::Error::new(io::ErrorKind::InvalidInput, x))?;
+	// SAFETY: `path` is nul terminated and `stat` is only read after statvfs succeeds
+	unsafe {
+		let mut stat = std::mem::zeroed::<libc::statvfs>();
+		if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		#[allow(clippy::unnecessary_cast)] // the field types differ between platforms
+		Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+	}
+}
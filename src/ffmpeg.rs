@@ -4,7 +4,7 @@ use std::{
 };
 
 use tokio::{
-	io::AsyncWriteExt,
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
 	process::{Child, Command},
 };
 
@@ -14,6 +14,11 @@ pub struct FFmpeg {
 	exec: PathBuf,
 }
 
+/// what `spawn_remove_silence` writes
+pub const OUTPUT_CONTAINER: &str = "mp4";
+pub const OUTPUT_VIDEO_CODEC: &str = "h264";
+pub const OUTPUT_AUDIO_CODEC: &str = "opus";
+
 const SILENCEDETECT_NOISE: &str = "-50dB";
 const SILENCEDETECT_DURATION: &str = "0.1";
 
@@ -62,6 +67,71 @@ impl OutputParser {
 	}
 }
 
+/// a line of ffmpeg's `-progress` output
+#[derive(Debug, PartialEq)]
+pub enum StatsParse {
+	Time(Duration),
+	Speed(f32),
+	/// the input has been read completely
+	End,
+	/// error or not any other stat
+	Other,
+}
+
+impl StatsParse {
+	pub fn parse_line(line: &str) -> Self {
+		let mut line = line.split('=');
+
+		let Some(lhs) = line.next() else {
+			return Self::Other;
+		};
+		let Some(rhs) = line.next() else {
+			return Self::Other;
+		};
+
+		// out_time_ms is the same as _us, bug in ffmpeg
+		match lhs {
+			"out_time_ms" => rhs
+				.parse::<i64>()
+				.map(|x| {
+					// time is negative during first frame
+					Self::Time(Duration::from_micros(x.max(0) as u64))
+				})
+				.unwrap_or(Self::Other),
+			"speed" => rhs
+				.trim_end_matches('x')
+				.parse::<f32>()
+				.map(Self::Speed)
+				.unwrap_or(Self::Other),
+			"progress" if rhs.trim() == "end" => Self::End,
+			_ => Self::Other,
+		}
+	}
+
+	/// `key=value`, as opposed to an error message
+	fn is_progress_line(line: &str) -> bool {
+		line.split_once('=').is_some_and(|(key, _)| {
+			!key.is_empty()
+				&& key.bytes().all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == b'_')
+		})
+	}
+}
+
+/// `Duration: 00:01:02.50, ...` from ffmpeg's description of the input
+fn parse_duration(stderr_text: &str) -> Option<Duration> {
+	let str = "Duration: ";
+	let i = stderr_text.find(str)?;
+	let split = stderr_text.split_at(i + str.len()).1.split_once(',')?.0.trim();
+	let split = split
+		.split(':')
+		.map(|x| x.parse::<f32>().ok())
+		.collect::<Option<Vec<_>>>()?;
+	let [hours, minutes, seconds] = split[..] else {
+		return None;
+	};
+	Some(Duration::from_secs_f32(hours * 3600.0 + minutes * 60.0 + seconds))
+}
+
 pub struct VideoAnalysis {
 	pub audible: Vec<Range<f32>>,
 	pub duration: Duration,
@@ -76,6 +146,25 @@ impl VideoAnalysis {
 		});
 		Self { audible, duration }
 	}
+
+	/// number of silent parts that are removed
+	pub fn cuts(&self) -> usize {
+		// shorter gaps are rounding errors
+		const MIN_GAP: f32 = 0.01;
+
+		let mut cuts = 0;
+		let mut prev_end = 0.0;
+		for range in &self.audible {
+			if range.start - prev_end > MIN_GAP {
+				cuts += 1;
+			}
+			prev_end = range.end;
+		}
+		if self.duration.as_secs_f32() - prev_end > MIN_GAP {
+			cuts += 1;
+		}
+		cuts
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -104,12 +193,34 @@ impl FFmpeg {
 		Self { input, output, exec }
 	}
 
-	/// returns an array of silent periods
-	pub async fn analyze_silence(&self) -> Result<VideoAnalysis, FFmpegError> {
+	/// length of the input, ffmpeg describes it even without an output
+	pub async fn probe_duration(&self) -> Result<Duration, FFmpegError> {
+		let mut ffmpeg = self.prepare_command();
+		ffmpeg.arg("-hide_banner");
+
+		// exits with an error, since no output is given
+		let output = ffmpeg.output().await?;
+		let stderr_text = String::from_utf8_lossy(&output.stderr).to_string();
+
+		parse_duration(&stderr_text).ok_or(FFmpegError::FFmpeg(stderr_text))
+	}
+
+	/// returns the audible periods of the input, `duration` long.
+	/// `on_stats` is called with each line of ffmpeg's progress
+	pub async fn analyze_silence(
+		&self,
+		duration: Duration,
+		mut on_stats: impl FnMut(StatsParse),
+	) -> Result<VideoAnalysis, FFmpegError> {
 		let mut ffmpeg = self.prepare_command();
 		ffmpeg
 			.arg("-vn")
 			.arg("-hide_banner")
+			.arg("-loglevel")
+			.arg("error")
+			.arg("-progress")
+			.arg("pipe:2")
+			.args(["-stats_period", "0.3"])
 			.arg("-af")
 			.arg(format!(
 				"silencedetect=noise={SILENCEDETECT_NOISE}:d={SILENCEDETECT_DURATION},ametadata=mode=print:file=-"
@@ -121,30 +232,36 @@ impl FFmpeg {
 		let mut ranges = Vec::new();
 		let mut parser_state = OutputParser::Start;
 
-		let output = ffmpeg.output().await?;
-		let stdout = output.stdout;
-		let stderr_text = String::from_utf8(output.stderr).unwrap();
+		let mut child = ffmpeg.spawn()?;
+		drop(child.stdin.take());
 
-		let perms = tokio::fs::metadata(&self.input).await.unwrap().permissions();
-		tracing::debug!("perms: {:?}", perms);
-		let status = ffmpeg.status().await.unwrap();
+		// silence is printed to stdout, progress and errors share stderr
+		let mut stdout = child.stdout.take().unwrap();
+		let stdout_reader = tokio::spawn(async move {
+			let mut buf = Vec::new();
+			stdout.read_to_end(&mut buf).await.map(|_| buf)
+		});
+
+		let mut err_lines = BufReader::new(child.stderr.take().unwrap()).lines();
+		let mut error_log = Vec::new();
+		while let Some(line) = err_lines.next_line().await? {
+			match StatsParse::parse_line(&line) {
+				StatsParse::Other if !StatsParse::is_progress_line(&line) => {
+					error_log.push(line)
+				}
+				StatsParse::Other => {}
+				stats => on_stats(stats),
+			}
+		}
+		let stdout = stdout_reader.await.map_err(io::Error::from)??;
+
+		let status = child.wait().await?;
 		if !status.success() {
 			tracing::debug!("silence status: {status:?}");
-			return Err(FFmpegError::FFmpeg(stderr_text));
+			return Err(FFmpegError::FFmpeg(error_log.join("\n")));
 		}
 
-		let video_duration = {
-			// coded in epic hurry
-			let str = "Duration: ";
-			let i = stderr_text.find(str).unwrap();
-			let split =
-				stderr_text.split_at(i + str.len()).1.split_once(',').unwrap().0.trim();
-			let split =
-				split.split(':').map(|x| x.parse::<f32>().unwrap()).collect::<Vec<_>>();
-			Duration::from_secs_f32(split[0] * 3600.0 + split[1] * 60.0 + split[2])
-		};
-
-		for line in String::from_utf8(stdout).unwrap().lines() {
+		for line in String::from_utf8_lossy(&stdout).lines() {
 			// lavfi.silence_*=
 			if line.starts_with("lavfi") {
 				let (next_state, range) = parser_state.next(line)?;
@@ -158,10 +275,10 @@ impl FFmpeg {
 		// sometimes the silencedetect doesn't output silence_end
 		// if close to end of video
 		if let OutputParser::End(start) = parser_state {
-			ranges.push(start..video_duration.as_secs_f32());
+			ranges.push(start..duration.as_secs_f32());
 		}
 
-		Ok(VideoAnalysis::new(ranges, video_duration))
+		Ok(VideoAnalysis::new(ranges, duration))
 	}
 
 	pub async fn spawn_remove_silence(
@@ -214,7 +331,7 @@ impl FFmpeg {
 			.arg("[audio]")
 			.args(["-c:v", "libx264", "-preset", "ultrafast"])
 			.args(["-c:a", "libopus"])
-			.args(["-f", "mp4"])
+			.args(["-f", OUTPUT_CONTAINER])
 			.arg(&self.output);
 
 		tracing::debug!("ffmpeg: {:?}", ffmpeg);
@@ -243,3 +360,71 @@ impl FFmpeg {
 		cmd
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_stats() {
+		let lines = [
+			// microseconds, despite the name
+			("out_time_ms=1500000", StatsParse::Time(Duration::from_millis(1500))),
+			("out_time_ms=-23219", StatsParse::Time(Duration::ZERO)),
+			("out_time_ms=N/A", StatsParse::Other),
+			("out_time_us=1500000", StatsParse::Other),
+			("speed=2.5x", StatsParse::Speed(2.5)),
+			("speed=N/A", StatsParse::Other),
+			("progress=continue", StatsParse::Other),
+			("progress=end", StatsParse::End),
+			("frame=12", StatsParse::Other),
+			("speed", StatsParse::Other),
+			("", StatsParse::Other),
+		];
+		for (line, expected) in lines {
+			assert_eq!(StatsParse::parse_line(line), expected, "{line:?}");
+		}
+	}
+
+	#[test]
+	fn progress_lines() {
+		assert!(StatsParse::is_progress_line("out_time_ms=N/A"));
+		assert!(StatsParse::is_progress_line("stream_0_0_q=-1.0"));
+		assert!(!StatsParse::is_progress_line("=1"));
+		assert!(!StatsParse::is_progress_line("Error while decoding stream #0:1: Invalid data found"));
+		assert!(!StatsParse::is_progress_line("[aac @ 0x5581] Input contains (near) NaN/+-Inf"));
+	}
+
+	#[test]
+	fn cuts() {
+		let analyses = [
+			// silence at both ends and in between
+			(vec![0.0..0.0, 2.0..5.0, 7.0..8.0], 10.0, 3),
+			(vec![0.0..4.0, 6.0..10.0], 10.0, 1),
+			// gaps shorter than 10ms are rounding
+			(vec![0.005..4.0, 4.005..9.995], 10.0, 0),
+			// all silent
+			(vec![], 10.0, 1),
+			(vec![], 0.0, 0),
+		];
+		for (audible, duration, expected) in analyses {
+			let analysis = VideoAnalysis { audible: audible.clone(), duration: Duration::from_secs_f32(duration) };
+			assert_eq!(analysis.cuts(), expected, "{audible:?} of {duration}s");
+		}
+	}
+
+	#[test]
+	fn parse_durations() {
+		let stderr = [
+			("  Duration: 00:01:02.50, start: 0.000000, bitrate: 1 kb/s", Some(62.5)),
+			("Input #0, mov,mp4\n  Duration: 01:00:00.00, start: 0.0", Some(3600.0)),
+			("  Duration: N/A, start: 0.000000, bitrate: N/A", None),
+			("  Duration: 00:01:02.50", None),
+			("  Duration: 01:02.50, start: 0.0", None),
+			("input.mp4: No such file or directory", None),
+		];
+		for (text, expected) in stderr {
+			assert_eq!(parse_duration(text), expected.map(Duration::from_secs_f32), "{text:?}");
+		}
+	}
+}
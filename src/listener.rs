@@ -0,0 +1,132 @@
+use std::{
+	io,
+	net::SocketAddr,
+	os::unix::fs::FileTypeExt,
+	path::{Path, PathBuf},
+	pin::Pin,
+	task::{ready, Context, Poll},
+};
+
+use axum::{
+	extract::State,
+	http::{header::HOST, uri::Authority, HeaderMap, StatusCode, Uri},
+	response::{IntoResponse, Redirect, Response},
+	routing::get,
+	Router,
+};
+use hyper::server::accept::Accept;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::acme::{self, AcmeChallenges};
+
+/// Adapts a [`UnixListener`] to hyper's [`Accept`]
+pub struct UnixAccept {
+	listener: UnixListener,
+	path: PathBuf,
+}
+
+impl UnixAccept {
+	/// Binds to `path`, removing a socket left over from a previous run
+	pub fn bind(path: &Path) -> io::Result<Self> {
+		match std::fs::symlink_metadata(path) {
+			Ok(metadata) if metadata.file_type().is_socket() => {
+				std::fs::remove_file(path)?;
+				tracing::debug!("removed stale socket {}", path.display());
+			}
+			Ok(_) => {
+				return Err(io::Error::new(
+					io::ErrorKind::AlreadyExists,
+					format!("{} exists and is not a socket", path.display()),
+				))
+			}
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+			Err(err) => return Err(err),
+		}
+		Ok(Self {
+			listener: UnixListener::bind(path)?,
+			path: path.to_path_buf(),
+		})
+	}
+}
+
+impl Accept for UnixAccept {
+	type Conn = UnixStream;
+	type Error = io::Error;
+
+	fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+		let (stream, _addr) = ready!(self.listener.poll_accept(cx))?;
+		Poll::Ready(Some(Ok(stream)))
+	}
+}
+
+impl Drop for UnixAccept {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+/// Listen for plain http on `addr` and redirect everything to https on `https_port`,
+/// except for acme challenges, which must be answered over http
+pub fn spawn_https_redirect(addr: SocketAddr, https_port: u16, acme_challenges: AcmeChallenges) {
+	let router = Router::new()
+		.route("/.well-known/acme-challenge/:token", get(acme::serve_challenge))
+		.with_state(acme_challenges)
+		.fallback(https_redirect)
+		.with_state(https_port);
+
+	tokio::task::spawn(async move {
+		tracing::info!("Redirecting http on {} to https", addr);
+		if let Err(err) = axum_server::bind(addr).serve(router.into_make_service()).await {
+			tracing::error!("https redirect listener on {} failed: {}", addr, err);
+		}
+	});
+}
+
+async fn https_redirect(State(https_port): State<u16>, headers: HeaderMap, uri: Uri) -> Response {
+	let Some(host) = headers
+		.get(HOST)
+		.and_then(|x| x.to_str().ok())
+		.and_then(|x| x.parse::<Authority>().ok())
+		.map(|x| x.host().to_string())
+	else {
+		return (StatusCode::BAD_REQUEST, "missing host header").into_response();
+	};
+
+	let path_and_query = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+	let location = if https_port == 443 {
+		format!("https://{host}{path_and_query}")
+	} else {
+		format!("https://{host}:{https_port}{path_and_query}")
+	};
+
+	Redirect::permanent(&location).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn temp_path(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("voice-listener-{}-{name}", std::process::id()))
+	}
+
+	#[tokio::test]
+	async fn replaces_stale_socket() {
+		let path = temp_path("stale.sock");
+		let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+		drop(stale);
+		let accept = UnixAccept::bind(&path).unwrap();
+		drop(accept);
+		assert!(!path.exists());
+	}
+
+	#[tokio::test]
+	async fn keeps_other_files() {
+		let path = temp_path("regular");
+		std::fs::write(&path, "data").unwrap();
+		let err = UnixAccept::bind(&path).err().unwrap();
+		assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+		assert_eq!(std::fs::read(&path).unwrap(), b"data");
+		std::fs::remove_file(path).unwrap();
+	}
+}
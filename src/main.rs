@@ -1,22 +1,34 @@
 #![deny(unused_crate_dependencies)]
 
+use std::process::ExitCode;
+
+use clap::Parser;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Layer};
 
+mod acme;
 mod avg;
+mod batch;
+mod cli;
 mod config;
+mod disk;
 mod ffmpeg;
+mod listener;
+mod metrics;
+mod ratelimit;
+mod storage;
 mod task;
+mod tls;
 mod web;
+mod webhook;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
 	/*
-	1. parse the config file or create a default one
-	2. check if ffmpeg and ffprobe are present, abort if not
-	3. set up logging to stdout and a log file, abort if file permissions denied
-	4. create the task manager thread
-	5. spin up the server, bind to port in the config and abort if it fails
-	6. create the stdin listener thread, do nothing if not tty
+	1. parse the arguments, subcommands other than serve return right away
+	2. parse the config file or create a default one, abort if it's invalid
+	3. set up logging to stdout and a log file
+	4. spin up the server, which restores saved tasks and starts the cleaner, console and watch folder
+	5. exit once the server has shut down
 	*/
 
 	// TODO: Better ffmpeg error reporing; maybe a separate ffmpeg log file
@@ -24,14 +36,47 @@ async fn main() {
 	// TODO: Implement re-encoding, since browsers don't like concatenated mp4.
 	// TODO: The rest of the frontend and API
 	// TODO: check if the file is suitable before processing
-	// TODO: upload files to bucket
+
+	let args = cli::Args::parse();
+	config::set_config_source(config::ConfigSource {
+		overrides: args.config_overrides(),
+		path: args.config,
+	});
+
+	if args.print_config {
+		return cli::print_config();
+	}
+	match args.command.unwrap_or(cli::Command::Serve) {
+		cli::Command::Serve => {}
+		cli::Command::Process { input, output } => return cli::process(input, output).await,
+		cli::Command::Analyze { input } => return cli::analyze(input).await,
+		cli::Command::Batch {
+			input_dir,
+			output_dir,
+			jobs,
+			report,
+		} => return cli::batch(input_dir, output_dir, jobs, report).await,
+		cli::Command::Check => return cli::check().await,
+	}
 
 	// println until logger is set up
 	println!("{} v{} by {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_AUTHORS"));
 	println!(";D");
 
 	// initialize config
-	config::reload_config().await;
+	match config::reload_config().await {
+		config::ConfigReloadResult::Ok { warnings, .. } => {
+			for warning in warnings {
+				println!("warning: {warning}");
+			}
+		}
+		config::ConfigReloadResult::Err(errors) => {
+			for error in errors {
+				println!("error: {error}");
+			}
+			return ExitCode::FAILURE;
+		}
+	}
 
 	// block to drop config_lock
 	{
@@ -39,16 +84,6 @@ async fn main() {
 
 		println!("config: {:#?}", config_lock);
 
-		if !config_lock.encoder_found() {
-			println!("error: encoder not found. specified path: \"{}\"", config_lock.ffmpeg_executable.display());
-			std::process::exit(1);
-		}
-
-		if !config_lock.web_dir_found() {
-			println!("error: web directory not found. specified path: \"{}\"", config_lock.web_root.display());
-			std::process::exit(1);
-		}
-
 		// initialize logging
 
 		let log_file_name = "log.txt";
@@ -85,4 +120,6 @@ async fn main() {
 	}
 
 	web::initialize_server().await;
+	// the console may still be blocked reading stdin, which would keep the runtime from shutting down
+	std::process::exit(0)
 }
@@ -0,0 +1,228 @@
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	sync::{
+		atomic::{AtomicI64, AtomicU64, Ordering},
+		Mutex,
+	},
+};
+
+/// Process wide metrics, rendered by the `/metrics` endpoint
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+	const fn new() -> Self {
+		Self(AtomicU64::new(0))
+	}
+
+	pub fn inc(&self) {
+		self.add(1);
+	}
+
+	pub fn add(&self, n: u64) {
+		self.0.fetch_add(n, Ordering::Relaxed);
+	}
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+	const fn new() -> Self {
+		Self(AtomicI64::new(0))
+	}
+
+	pub fn inc(&self) {
+		self.0.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn dec(&self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	/// increments now and decrements when the guard is dropped
+	pub fn track(&'static self) -> GaugeGuard {
+		self.inc();
+		GaugeGuard(self)
+	}
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+	fn drop(&mut self) {
+		self.0.dec();
+	}
+}
+
+/// Counters told apart by the value of a single label
+pub struct LabeledCounter {
+	label: &'static str,
+	values: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl LabeledCounter {
+	const fn new(label: &'static str) -> Self {
+		Self {
+			label,
+			values: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	pub fn inc(&self, value: &'static str) {
+		*self.values.lock().unwrap().entry(value).or_default() += 1;
+	}
+}
+
+struct HistogramState<const N: usize> {
+	/// not cumulative
+	buckets: [u64; N],
+	/// above the last bound
+	overflow: u64,
+	sum: f64,
+}
+
+pub struct Histogram<const N: usize> {
+	/// upper bounds, ascending
+	bounds: [f64; N],
+	state: Mutex<HistogramState<N>>,
+}
+
+impl<const N: usize> Histogram<N> {
+	const fn new(bounds: [f64; N]) -> Self {
+		Self {
+			bounds,
+			state: Mutex::new(HistogramState {
+				buckets: [0; N],
+				overflow: 0,
+				sum: 0.0,
+			}),
+		}
+	}
+
+	pub fn observe(&self, value: f64) {
+		let mut state = self.state.lock().unwrap();
+		match self.bounds.iter().position(|x| value <= *x) {
+			Some(i) => state.buckets[i] += 1,
+			None => state.overflow += 1,
+		}
+		state.sum += value;
+	}
+}
+
+/// Writes metrics in the prometheus text format
+pub struct MetricsWriter(String);
+
+impl MetricsWriter {
+	pub fn new() -> Self {
+		Self(String::new())
+	}
+
+	fn header(&mut self, name: &str, kind: &str, help: &str) {
+		let _ = writeln!(self.0, "# HELP {name} {help}");
+		let _ = writeln!(self.0, "# TYPE {name} {kind}");
+	}
+
+	pub fn counter(&mut self, name: &str, help: &str, counter: &Counter) {
+		self.header(name, "counter", help);
+		let _ = writeln!(self.0, "{name} {}", counter.0.load(Ordering::Relaxed));
+	}
+
+	pub fn labeled_counter(&mut self, name: &str, help: &str, counter: &LabeledCounter) {
+		self.header(name, "counter", help);
+		for (value, count) in counter.values.lock().unwrap().iter() {
+			let _ = writeln!(self.0, "{name}{{{}=\"{value}\"}} {count}", counter.label);
+		}
+	}
+
+	pub fn gauge(&mut self, name: &str, help: &str, value: i64) {
+		self.header(name, "gauge", help);
+		let _ = writeln!(self.0, "{name} {value}");
+	}
+
+	/// `values` are pairs of label value and gauge value
+	pub fn labeled_gauge<'a>(
+		&mut self,
+		name: &str,
+		help: &str,
+		label: &str,
+		values: impl IntoIterator<Item = (&'a str, u64)>,
+	) {
+		self.header(name, "gauge", help);
+		for (value, x) in values {
+			let _ = writeln!(self.0, "{name}{{{label}=\"{value}\"}} {x}");
+		}
+	}
+
+	pub fn histogram<const N: usize>(&mut self, name: &str, help: &str, histogram: &Histogram<N>) {
+		self.header(name, "histogram", help);
+		let state = histogram.state.lock().unwrap();
+		let mut cumulative = 0;
+		for (bound, count) in histogram.bounds.iter().zip(state.buckets) {
+			cumulative += count;
+			let _ = writeln!(self.0, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+		}
+		cumulative += state.overflow;
+		let _ = writeln!(self.0, "{name}_bucket{{le=\"+Inf\"}} {cumulative}");
+		let _ = writeln!(self.0, "{name}_sum {}", state.sum);
+		let _ = writeln!(self.0, "{name}_count {cumulative}");
+	}
+
+	pub fn finish(self) -> String {
+		self.0
+	}
+}
+
+/// seconds, for analysis and encoding times
+const DURATION_BUCKETS: [f64; 10] = [1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0];
+/// multiples of realtime
+const SPEED_BUCKETS: [f64; 9] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+
+pub struct Metrics {
+	pub uploads_accepted: Counter,
+	/// by reason
+	pub uploads_rejected: LabeledCounter,
+	/// of accepted uploads
+	pub upload_bytes: Counter,
+	/// by final status
+	pub tasks_finished: LabeledCounter,
+	pub analysis_seconds: Histogram<10>,
+	pub encode_seconds: Histogram<10>,
+	pub encode_speed: Histogram<9>,
+	pub active_websockets: Gauge,
+	/// by what was deleted
+	pub cleanup_deletions: LabeledCounter,
+}
+
+impl Metrics {
+	const fn new() -> Self {
+		Self {
+			uploads_accepted: Counter::new(),
+			uploads_rejected: LabeledCounter::new("reason"),
+			upload_bytes: Counter::new(),
+			tasks_finished: LabeledCounter::new("status"),
+			analysis_seconds: Histogram::new(DURATION_BUCKETS),
+			encode_seconds: Histogram::new(DURATION_BUCKETS),
+			encode_speed: Histogram::new(SPEED_BUCKETS),
+			active_websockets: Gauge::new(),
+			cleanup_deletions: LabeledCounter::new("kind"),
+		}
+	}
+
+	pub fn write(&self, writer: &mut MetricsWriter) {
+		writer.counter("voice_uploads_accepted_total", "Uploads that started a task", &self.uploads_accepted);
+		writer.labeled_counter("voice_uploads_rejected_total", "Uploads refused, by reason", &self.uploads_rejected);
+		writer.counter("voice_upload_bytes_total", "Bytes of accepted uploads", &self.upload_bytes);
+		writer.labeled_counter("voice_tasks_finished_total", "Tasks by final status", &self.tasks_finished);
+		writer.histogram("voice_analysis_duration_seconds", "Time spent looking for silence", &self.analysis_seconds);
+		writer.histogram("voice_encode_duration_seconds", "Time spent encoding the output", &self.encode_seconds);
+		writer.histogram("voice_encode_speed", "Encoding speed as a multiple of realtime", &self.encode_speed);
+		writer.gauge(
+			"voice_active_websockets",
+			"Open status websockets",
+			self.active_websockets.0.load(Ordering::Relaxed),
+		);
+		writer.labeled_counter("voice_cleanup_deletions_total", "Tasks and files removed by cleanup", &self.cleanup_deletions);
+	}
+}
@@ -0,0 +1,265 @@
+use std::{
+	collections::HashMap,
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use axum::{
+	extract::{ConnectInfo, State},
+	http::{header::RETRY_AFTER, HeaderMap, Request, StatusCode},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+
+use crate::config::{RateLimit, RateLimitConfig, CONFIG};
+use crate::metrics::METRICS;
+
+/// Which of the configured limits a [`RateLimiter`] enforces
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitKind {
+	Submit,
+	Status,
+	WebSocket,
+}
+
+impl RateLimitKind {
+	fn limit(self, config: &RateLimitConfig) -> RateLimit {
+		match self {
+			Self::Submit => config.submit,
+			Self::Status => config.status,
+			Self::WebSocket => config.websocket,
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl Bucket {
+	fn full(limit: RateLimit) -> Self {
+		Self {
+			tokens: limit.burst as f64,
+			last_refill: Instant::now(),
+		}
+	}
+
+	fn refill(&mut self, limit: RateLimit) {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * limit.per_second()).min(limit.burst as f64);
+		self.last_refill = now;
+	}
+
+	/// take a token, or return how long to wait until one is available
+	fn take(&mut self, limit: RateLimit) -> Result<(), Duration> {
+		self.refill(limit);
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			return Ok(());
+		}
+		let per_second = limit.per_second();
+		if per_second <= 0.0 {
+			// never refills, tell the client to come back much later
+			return Err(Duration::from_secs(60 * 60));
+		}
+		Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+	}
+}
+
+/// Token bucket rate limiter keyed by client ip.
+///
+/// Limits are read from the config on every check,
+/// so they follow config reloads.
+#[derive(Debug)]
+pub struct RateLimiter {
+	kind: RateLimitKind,
+	buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+	pub fn new(kind: RateLimitKind) -> Self {
+		Self {
+			kind,
+			buckets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	fn check(&self, ip: IpAddr, limit: RateLimit) -> Result<(), Duration> {
+		let mut buckets = self.buckets.lock().unwrap();
+		buckets.entry(ip).or_insert_with(|| Bucket::full(limit)).take(limit)
+	}
+
+	/// forget clients whose buckets have refilled completely
+	async fn prune(&self) {
+		let limit = self.kind.limit(&CONFIG.read().await.rate_limit);
+		let mut buckets = self.buckets.lock().unwrap();
+		buckets.retain(|_, bucket| {
+			bucket.refill(limit);
+			bucket.tokens < limit.burst as f64
+		});
+	}
+}
+
+/// One [`RateLimiter`] per limited endpoint group
+#[derive(Debug, Clone)]
+pub struct RateLimiters {
+	pub submit: Arc<RateLimiter>,
+	pub status: Arc<RateLimiter>,
+	pub websocket: Arc<RateLimiter>,
+}
+
+impl RateLimiters {
+	pub fn new() -> Self {
+		Self {
+			submit: Arc::new(RateLimiter::new(RateLimitKind::Submit)),
+			status: Arc::new(RateLimiter::new(RateLimitKind::Status)),
+			websocket: Arc::new(RateLimiter::new(RateLimitKind::WebSocket)),
+		}
+	}
+
+	pub async fn prune(&self) {
+		self.submit.prune().await;
+		self.status.prune().await;
+		self.websocket.prune().await;
+	}
+}
+
+/// Determine the client's address.
+///
+/// If a trusted proxy header is configured and present, the last address in it is used,
+/// because that is the one appended by our own proxy. Otherwise the peer address is used.
+/// Connections over a unix socket have no peer address and count as localhost.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trusted_proxy_header: Option<&str>) -> IpAddr {
+	trusted_proxy_header
+		.and_then(|name| headers.get(name))
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.rsplit(',').next())
+		.and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+		.or(peer.map(|x| x.ip()))
+		.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+pub async fn rate_limit_middleware<B>(
+	State(limiter): State<Arc<RateLimiter>>,
+	peer: Option<ConnectInfo<SocketAddr>>,
+	request: Request<B>,
+	next: Next<B>,
+) -> Response {
+	let (limit, ip) = {
+		let config_lock = CONFIG.read().await;
+		let config = &config_lock.rate_limit;
+		if !config.enabled {
+			return next.run(request).await;
+		}
+		let ip = client_ip(request.headers(), peer.map(|x| x.0), config.trusted_proxy_header.as_deref());
+		(limiter.kind.limit(config), ip)
+	};
+
+	match limiter.check(ip, limit) {
+		Ok(()) => next.run(request).await,
+		Err(retry_after) => {
+			// round up, so the client doesn't come back too early
+			let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+			tracing::info!("rate limited {ip} on {:?}, retry after {retry_after_secs}s", limiter.kind);
+			if let RateLimitKind::Submit = limiter.kind {
+				METRICS.uploads_rejected.inc("rate_limited");
+			}
+			(StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, retry_after_secs.to_string())], "too many requests")
+				.into_response()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LIMIT: RateLimit = RateLimit {
+		burst: 3,
+		per_minute: 60,
+	};
+
+	#[test]
+	fn burst_then_limited() {
+		let mut bucket = Bucket::full(LIMIT);
+		for _ in 0..3 {
+			assert!(bucket.take(LIMIT).is_ok());
+		}
+		let retry_after = bucket.take(LIMIT).unwrap_err();
+		assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1));
+	}
+
+	#[test]
+	fn refills_over_time() {
+		let mut bucket = Bucket::full(LIMIT);
+		for _ in 0..3 {
+			bucket.take(LIMIT).unwrap();
+		}
+		bucket.last_refill -= Duration::from_secs(2);
+		assert!(bucket.take(LIMIT).is_ok());
+		assert!(bucket.take(LIMIT).is_ok());
+		assert!(bucket.take(LIMIT).is_err());
+	}
+
+	#[test]
+	fn refill_stops_at_burst() {
+		let mut bucket = Bucket::full(LIMIT);
+		bucket.last_refill -= Duration::from_secs(60);
+		bucket.refill(LIMIT);
+		assert_eq!(bucket.tokens, 3.0);
+	}
+
+	#[test]
+	fn no_refill_rate() {
+		let limit = RateLimit {
+			burst: 1,
+			per_minute: 0,
+		};
+		let mut bucket = Bucket::full(limit);
+		bucket.take(limit).unwrap();
+		assert_eq!(bucket.take(limit), Err(Duration::from_secs(60 * 60)));
+	}
+
+	#[test]
+	fn limiter_keeps_clients_apart() {
+		let limiter = RateLimiter::new(RateLimitKind::Submit);
+		let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+		for _ in 0..3 {
+			limiter.check(a, LIMIT).unwrap();
+		}
+		assert!(limiter.check(a, LIMIT).is_err());
+		assert!(limiter.check(b, LIMIT).is_ok());
+	}
+
+	fn forwarded(value: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert("X-Forwarded-For", value.parse().unwrap());
+		headers
+	}
+
+	#[test]
+	fn client_ip_uses_rightmost_forwarded_entry() {
+		let peer = Some("192.168.1.1:1234".parse().unwrap());
+		// the left entries are set by the client and can't be trusted
+		let headers = forwarded("1.1.1.1, 2.2.2.2,3.3.3.3");
+		assert_eq!(client_ip(&headers, peer, Some("X-Forwarded-For")), "3.3.3.3".parse::<IpAddr>().unwrap());
+		assert_eq!(client_ip(&forwarded("::1"), peer, Some("x-forwarded-for")), "::1".parse::<IpAddr>().unwrap());
+	}
+
+	#[test]
+	fn client_ip_falls_back_to_peer() {
+		let peer = Some("192.168.1.1:1234".parse().unwrap());
+		let expected = "192.168.1.1".parse::<IpAddr>().unwrap();
+		// header not trusted
+		assert_eq!(client_ip(&forwarded("3.3.3.3"), peer, None), expected);
+		// garbage in the last entry
+		assert_eq!(client_ip(&forwarded("3.3.3.3, nonsense"), peer, Some("X-Forwarded-For")), expected);
+		assert_eq!(client_ip(&HeaderMap::new(), peer, Some("X-Forwarded-For")), expected);
+		// unix socket
+		assert_eq!(client_ip(&HeaderMap::new(), None, None), IpAddr::V4(Ipv4Addr::LOCALHOST));
+	}
+}
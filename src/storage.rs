@@ -0,0 +1,107 @@
+use std::{io, path::Path};
+
+use crate::{
+	config::{StorageBackend, StorageConfig},
+	task::TaskId,
+};
+
+mod s3;
+
+pub use s3::S3Storage;
+
+/// Where inputs and finished outputs are kept.
+///
+/// Inputs and outputs are always in `inputs_dir` and `outputs_dir` first, ffmpeg works on local files.
+/// With a remote backend inputs are copied there while the task runs, unless `retention.inputs_minutes` is 0,
+/// and deleted with the local file.
+/// Outputs are moved there once the task completes, so readers should try the local file before asking the backend.
+pub enum Storage {
+	Local,
+	S3(Box<S3Storage>),
+}
+
+fn input_key(task_id: TaskId) -> String {
+	format!("inputs/{task_id}")
+}
+
+fn output_key(task_id: TaskId) -> String {
+	format!("outputs/{task_id}")
+}
+
+impl Storage {
+	pub fn from_config(config: &StorageConfig) -> Self {
+		match config.backend {
+			StorageBackend::Local => Self::Local,
+			StorageBackend::S3 => Self::S3(Box::new(S3Storage::new(config.s3.clone()))),
+		}
+	}
+
+	/// Copy a task's input at `local_path` into storage, the local file stays for ffmpeg
+	pub async fn store_input(&self, task_id: TaskId, local_path: &Path) -> io::Result<()> {
+		match self {
+			Self::Local => Ok(()),
+			Self::S3(s3) => {
+				s3.put_file(&s3.key(&input_key(task_id)), local_path, "application/octet-stream").await?;
+				tracing::debug!("uploaded input of task {} to s3", task_id);
+				Ok(())
+			}
+		}
+	}
+
+	/// Delete a task's input at `local_path` and its stored copy.
+	/// Fails with [`io::ErrorKind::NotFound`] if the local file was already deleted
+	pub async fn delete_input(&self, task_id: TaskId, local_path: &Path) -> io::Result<()> {
+		tokio::fs::remove_file(local_path).await?;
+		match self {
+			Self::Local => Ok(()),
+			Self::S3(s3) => s3.delete(&s3.key(&input_key(task_id))).await,
+		}
+	}
+
+	/// Move a completed task's output from `local_path` into storage
+	pub async fn store_output(&self, task_id: TaskId, local_path: &Path) -> io::Result<()> {
+		match self {
+			Self::Local => Ok(()),
+			Self::S3(s3) => {
+				s3.put_file(&s3.key(&output_key(task_id)), local_path, "video/mp4").await?;
+				tracing::debug!("uploaded output of task {} to s3", task_id);
+				tokio::fs::remove_file(local_path).await
+			}
+		}
+	}
+
+	/// Delete a stored output. Local files are left to the cleanup of `outputs_dir`
+	pub async fn delete_output(&self, task_id: TaskId) -> io::Result<()> {
+		match self {
+			Self::Local => Ok(()),
+			Self::S3(s3) => s3.delete(&s3.key(&output_key(task_id))).await,
+		}
+	}
+
+	/// Copy an output to `destination`, from `local_path` if it's still there
+	pub async fn copy_output(&self, task_id: TaskId, local_path: &Path, destination: &Path) -> io::Result<()> {
+		match tokio::fs::copy(local_path, destination).await {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+			result => return result.map(|_| ()),
+		}
+		match self {
+			Self::Local => Err(io::ErrorKind::NotFound.into()),
+			Self::S3(s3) => {
+				let mut response = s3.get(&s3.key(&output_key(task_id))).await?;
+				let mut file = tokio::fs::File::create(destination).await?;
+				while let Some(chunk) = response.chunk().await.map_err(io::Error::other)? {
+					tokio::io::AsyncWriteExt::write_all(&mut file, &chunk).await?;
+				}
+				Ok(())
+			}
+		}
+	}
+
+	/// The key of a task's output in the bucket, `None` for local storage
+	pub fn s3_output(&self, task_id: TaskId) -> Option<(&S3Storage, String)> {
+		match self {
+			Self::Local => None,
+			Self::S3(s3) => Some((s3, s3.key(&output_key(task_id)))),
+		}
+	}
+}
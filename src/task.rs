@@ -1,24 +1,34 @@
 use std::{
 	fmt::Display,
-	path::{Path, PathBuf},
+	path::PathBuf,
 	sync::Arc,
-	time::Duration,
+	time::{Duration, Instant},
 };
 
 use rand::Rng;
 use tokio::{
 	io::{self, AsyncBufReadExt, BufReader},
-	sync::RwLock,
+	sync::watch,
 };
 
-use crate::ffmpeg::{FFmpeg, FFmpegError};
-
-pub type TaskUpdateSender = tokio::sync::broadcast::Sender<TaskUpdateMessage>;
+use crate::{
+	avg::{DisplayDuration, SlidingAverage},
+	ffmpeg::{self, FFmpeg, FFmpegError, StatsParse},
+	metrics::METRICS,
+};
 
-#[derive(Debug)]
-struct InnerTask {
-	last_status: TaskStatus,
-	task_update_tx: TaskUpdateSender,
+/// Latest status of a task. Receivers only see the newest value,
+/// so slow ones skip progress updates but can't miss the final status
+pub type TaskStatusReceiver = watch::Receiver<VersionedStatus>;
+type TaskStatusSender = watch::Sender<VersionedStatus>;
+
+#[derive(Clone, Debug)]
+pub struct VersionedStatus {
+	/// number of status updates so far
+	pub version: u64,
+	pub status: TaskStatus,
+	/// set with the final status, errors don't carry an end time of their own
+	pub finished_at: Option<time::OffsetDateTime>,
 }
 
 /// Represents a task that is currently running
@@ -29,9 +39,39 @@ struct InnerTask {
 #[derive(Debug)]
 pub struct Task {
 	pub id: TaskId,
+	/// who created the task: the client's address, `watch` or `cli`
+	submitter: String,
 	start_time: time::OffsetDateTime,
+	/// overrides how long the output is kept after completing
+	keep_for: Option<time::Duration>,
 	tokio_handle: tokio::task::JoinHandle<()>,
-	inner: Arc<RwLock<InnerTask>>,
+	status_tx: Arc<TaskStatusSender>,
+}
+
+/// Uploads are tracked by the client, a task only exists once its input has arrived
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskPhase {
+	/// reading the input's duration
+	Probing,
+	/// looking for silence
+	Analyzing,
+	/// writing the output without the silence
+	Encoding,
+	/// the encoder has read all of the input and is finishing the output file
+	Finalizing,
+}
+
+impl Display for TaskPhase {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let name = match self {
+			Self::Probing => "probing",
+			Self::Analyzing => "analyzing",
+			Self::Encoding => "encoding",
+			Self::Finalizing => "finalizing",
+		};
+		write!(f, "{name}")
+	}
 }
 
 #[derive(Clone, Debug, serde::Serialize)]
@@ -39,16 +79,66 @@ pub struct Task {
 #[serde(tag = "type", content = "data")]
 pub enum TaskStatus {
 	InProgress {
+		phase: TaskPhase,
+		/// of the current phase, from 0 to 1
 		progress: f32,
+		/// smoothed speed of the current phase, as a multiple of realtime
 		speed: f32,
+		/// estimated time left in the current phase, `None` until it has made some progress.
+		/// the encoding speed isn't known during analysis, so there's no estimate for the whole task
+		phase_eta_seconds: Option<f32>,
+		/// since the task started, including analysis
+		elapsed_seconds: f32,
 	},
 	#[serde(serialize_with = "display_serialize")]
 	Error(FFmpegError),
 	Completed {
 		end_time: time::OffsetDateTime,
+		#[serde(flatten)]
+		summary: ConversionSummary,
+	},
+	Cancelled {
+		end_time: time::OffsetDateTime,
 	},
 }
 
+impl TaskStatus {
+	/// when the task finished, `None` while it's in progress or if it failed
+	pub fn end_time(&self) -> Option<time::OffsetDateTime> {
+		match self {
+			Self::Completed { end_time, .. } | Self::Cancelled { end_time } => Some(*end_time),
+			_ => None,
+		}
+	}
+}
+
+/// What a finished conversion did, durations in seconds
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ConversionSummary {
+	pub original_duration: f32,
+	pub output_duration: f32,
+	/// share of the original duration that was cut, from 0 to 100
+	pub removed_percent: f32,
+	/// silent parts removed
+	pub cuts: usize,
+	/// in bytes
+	pub output_size: u64,
+	pub container: String,
+	pub video_codec: String,
+	pub audio_codec: String,
+	/// from the start of the task until the output was written
+	pub processing_time: f32,
+}
+
+/// 0 for an empty input, rather than NaN
+fn removed_percent(original_duration: f32, output_duration: f32) -> f32 {
+	if original_duration > 0.0 {
+		(1.0 - output_duration / original_duration) * 100.0
+	} else {
+		0.0
+	}
+}
+
 fn display_serialize<S: serde::Serializer, T: Display>(x: &T, s: S) -> Result<S::Ok, S::Error> {
 	s.collect_str(x)
 }
@@ -67,39 +157,95 @@ macro_rules! try_else {
 	};
 }
 
-enum StatsParse {
-	Time(Duration),
-	Speed(f32),
-	/// error or not any other stat
-	Other,
+/// Progress of a phase that goes through `total` seconds of media
+struct PhaseProgress {
+	total: f32,
+	progress: f32,
+	speed: f32,
+	/// wall time it takes to process a second of media
+	time_per_media_second: SlidingAverage,
+	last_sample: Option<(Instant, Duration)>,
+	eta: Option<Duration>,
 }
 
-impl StatsParse {
-	fn parse_line(line: &str) -> Self {
-		let mut line = line.split('=');
+impl PhaseProgress {
+	fn new(total: f32) -> Self {
+		Self {
+			total,
+			progress: 0.0,
+			speed: 0.0,
+			time_per_media_second: SlidingAverage::new(10),
+			last_sample: None,
+			eta: None,
+		}
+	}
 
-		let Some(lhs) = line.next() else {
-			return Self::Other;
-		};
-		let Some(rhs) = line.next() else {
-			return Self::Other;
-		};
+	/// returns whether anything changed
+	fn update(&mut self, stats: StatsParse) -> bool {
+		self.update_at(stats, Instant::now())
+	}
 
-		match lhs {
-			"out_time_ms" => rhs
-				.parse::<i64>()
-				.map(|x| {
-					// time is negative during first frame
-					Self::Time(Duration::from_micros(x.max(0) as u64))
-				})
-				.unwrap_or(Self::Other),
-			"speed" => rhs
-				.trim_end_matches('x')
-				.parse::<f32>()
-				.map(Self::Speed)
-				.unwrap_or(Self::Other),
-			_ => Self::Other,
+	fn update_at(&mut self, stats: StatsParse, now: Instant) -> bool {
+		match stats {
+			// ffmpeg's own speed is only used until there's a smoothed one
+			StatsParse::Speed(new_speed) if self.eta.is_none() => {
+				self.speed = new_speed;
+			}
+			StatsParse::Time(new_time) => {
+				// may overflow a little somtimes
+				self.progress = (new_time.as_secs_f32() / self.total).min(1.0);
+
+				match self.last_sample {
+					Some((last_wall_time, last_time)) if new_time > last_time => {
+						let sample = (now - last_wall_time).div_f32((new_time - last_time).as_secs_f32());
+						let average = self.time_per_media_second.push(sample);
+						self.speed = 1.0 / average.as_secs_f32();
+						self.eta = Some(average.mul_f32((self.total - new_time.as_secs_f32()).max(0.0)));
+						self.last_sample = Some((now, new_time));
+					}
+					Some(_) => {}
+					None => self.last_sample = Some((now, new_time)),
+				}
+			}
+			_ => return false,
 		}
+		true
+	}
+}
+
+/// Publishes progress, at most once per `interval` unless forced
+struct ProgressReporter<'a> {
+	status_tx: &'a TaskStatusSender,
+	started: Instant,
+	interval: Duration,
+	last_published: Option<Instant>,
+}
+
+impl ProgressReporter<'_> {
+	fn report(&mut self, phase: TaskPhase, progress: &PhaseProgress) {
+		if self.last_published.is_some_and(|x| x.elapsed() < self.interval) {
+			return;
+		}
+		self.force_report(phase, progress);
+	}
+
+	/// for phase changes, which shouldn't be dropped
+	fn force_report(&mut self, phase: TaskPhase, progress: &PhaseProgress) {
+		self.last_published = Some(Instant::now());
+		tracing::debug!(
+			"{phase}: {:.1}% at {:.2}x, eta {}",
+			progress.progress * 100.0,
+			progress.speed,
+			progress.eta.map_or("unknown".to_string(), |x| DisplayDuration(x).to_string())
+		);
+		let status = TaskStatus::InProgress {
+			phase,
+			progress: progress.progress,
+			speed: progress.speed,
+			phase_eta_seconds: progress.eta.map(|x| x.as_secs_f32()),
+			elapsed_seconds: self.started.elapsed().as_secs_f32(),
+		};
+		Task::update_status(self.status_tx, status);
 	}
 }
 
@@ -107,109 +253,198 @@ impl Task {
 	/// initialize and start a new task
 	/// also start a tokio task
 	/// to observe it
+	///
+	/// progress is published at most once per `progress_interval`
 	pub fn new(
 		input_file: PathBuf,
+		output_file: PathBuf,
 		task_id: TaskId,
-		outputs_dir: &Path,
 		ffmpeg_executable: PathBuf,
+		progress_interval: Duration,
+		submitter: String,
+		keep_for: Option<time::Duration>,
 	) -> io::Result<Task> {
-		let output_file = outputs_dir.join(format!("{task_id}"));
-
-		let last_status = TaskStatus::InProgress {
-			progress: 0.0,
-			speed: 0.0,
-		};
-		let task_update_tx = tokio::sync::broadcast::Sender::new(8);
-
-		let inner_task = Arc::new(RwLock::new(InnerTask {
-			last_status,
-			task_update_tx: task_update_tx.clone(),
-		}));
+		let (status_tx, _) = watch::channel(VersionedStatus {
+			version: 0,
+			status: TaskStatus::InProgress {
+				phase: TaskPhase::Probing,
+				progress: 0.0,
+				speed: 0.0,
+				phase_eta_seconds: None,
+				elapsed_seconds: 0.0,
+			},
+			finished_at: None,
+		});
+		let status_tx = Arc::new(status_tx);
 
 		let tokio_handle = tokio::task::spawn({
-			let inner_task = inner_task.clone();
+			let status_tx = status_tx.clone();
 			async move {
 				let conversion_result =
-					Self::run_conversion(input_file, output_file, ffmpeg_executable, inner_task.clone(), task_id).await;
+					Self::run_conversion(input_file, output_file, ffmpeg_executable, &status_tx, progress_interval)
+						.await;
 
 				// ignore send result
 				let final_status = match conversion_result {
-					Ok(_) => TaskStatus::Completed {
-						end_time: time::OffsetDateTime::now_utc(),
-					},
-					Err(msg) => TaskStatus::Error(msg),
+					Ok(summary) => {
+						METRICS.tasks_finished.inc("completed");
+						TaskStatus::Completed {
+							end_time: time::OffsetDateTime::now_utc(),
+							summary,
+						}
+					}
+					Err(msg) => {
+						METRICS.tasks_finished.inc("error");
+						TaskStatus::Error(msg)
+					}
 				};
 
 				tracing::debug!("sent last update: {final_status:?}");
-				Self::update_status(&mut *inner_task.write().await, task_id, final_status).await;
+				Self::update_status(&status_tx, final_status);
 			}
 		});
 
 		Ok(Self {
 			tokio_handle,
 			id: task_id,
-			inner: inner_task,
+			submitter,
+			keep_for,
+			status_tx,
 			start_time: time::OffsetDateTime::now_utc(),
 		})
 	}
 
-	pub async fn cancel(self) {
+	/// a task that completed before a restart, nothing is run
+	pub fn restored(
+		task_id: TaskId,
+		submitter: String,
+		start_time: time::OffsetDateTime,
+		keep_for: Option<time::Duration>,
+		end_time: time::OffsetDateTime,
+		summary: ConversionSummary,
+	) -> Task {
+		let (status_tx, _) = watch::channel(VersionedStatus {
+			version: 0,
+			status: TaskStatus::Completed { end_time, summary },
+			finished_at: Some(end_time),
+		});
+
+		Self {
+			tokio_handle: tokio::task::spawn(async {}),
+			id: task_id,
+			submitter,
+			keep_for,
+			status_tx: Arc::new(status_tx),
+			start_time,
+		}
+	}
+
+	/// stop the encoder and mark the task as cancelled.
+	/// does nothing if the task has already finished
+	pub fn cancel(&self) {
 		self.tokio_handle.abort();
-		let mut this = self.inner.write().await;
-		Self::update_status(
-			&mut this,
-			self.id,
-			TaskStatus::Completed {
-				end_time: time::OffsetDateTime::now_utc(),
-			},
-		)
-		.await;
+		self.status_tx.send_if_modified(|x| {
+			if !matches!(x.status, TaskStatus::InProgress { .. }) {
+				return false;
+			}
+			let now = time::OffsetDateTime::now_utc();
+			x.version += 1;
+			x.status = TaskStatus::Cancelled { end_time: now };
+			x.finished_at = Some(now);
+			METRICS.tasks_finished.inc("cancelled");
+			true
+		});
+	}
+
+	/// cancel and wait until the encoder has been killed
+	pub async fn stop(self) {
+		self.cancel();
+		let _ = self.tokio_handle.await;
+	}
+
+	/// the current status counts as seen, `changed()` resolves on the next update
+	pub fn subscribe(&self) -> TaskStatusReceiver {
+		self.status_tx.subscribe()
+	}
+
+	pub fn start_time(&self) -> time::OffsetDateTime {
+		self.start_time
+	}
+
+	pub fn submitter(&self) -> &str {
+		&self.submitter
 	}
 
-	pub async fn subscribe(&self) -> tokio::sync::broadcast::Receiver<TaskUpdateMessage> {
-		self.inner.read().await.task_update_tx.subscribe()
+	pub fn keep_for(&self) -> Option<time::Duration> {
+		self.keep_for
 	}
 
-	pub async fn last_status(&self) -> TaskStatus {
-		self.inner.read().await.last_status.clone()
+	/// when the task stopped, whatever the reason
+	pub fn finished_at(&self) -> Option<time::OffsetDateTime> {
+		self.status_tx.borrow().finished_at
 	}
 
-	pub fn last_status_blocking(&self) -> TaskStatus {
-		self.inner.blocking_read().last_status.clone()
+	pub fn last_status(&self) -> TaskStatus {
+		self.status_tx.borrow().status.clone()
 	}
 
 	pub fn gen_id() -> TaskId {
 		rand::thread_rng().gen()
 	}
 
-	async fn update_status(inner: &mut InnerTask, id: TaskId, new_status: TaskStatus) {
-		inner.last_status = new_status.clone();
-		let _res = inner.task_update_tx.send((id, new_status.clone()));
+	fn update_status(status_tx: &TaskStatusSender, new_status: TaskStatus) {
+		status_tx.send_modify(|x| {
+			if !matches!(new_status, TaskStatus::InProgress { .. }) {
+				x.finished_at = Some(time::OffsetDateTime::now_utc());
+			}
+			x.version += 1;
+			x.status = new_status;
+		});
 	}
 
 	async fn run_conversion(
 		input_file: PathBuf,
 		output_file: PathBuf,
 		ffmpeg_executable: PathBuf,
-		inner: Arc<RwLock<InnerTask>>,
-		task_id: TaskId,
-	) -> Result<(), FFmpegError> {
+		status_tx: &TaskStatusSender,
+		progress_interval: Duration,
+	) -> Result<ConversionSummary, FFmpegError> {
 		tracing::debug!("begin task");
+		let mut reporter = ProgressReporter {
+			status_tx,
+			started: Instant::now(),
+			interval: progress_interval,
+			last_published: None,
+		};
+
+		let ffmpeg = FFmpeg::new(input_file.to_path_buf(), output_file.clone(), ffmpeg_executable.to_path_buf());
 
-		let ffmpeg = FFmpeg::new(input_file.to_path_buf(), output_file, ffmpeg_executable.to_path_buf());
+		let duration = try_else!(ffmpeg.probe_duration().await, err, {
+			tracing::info!("probe error: {:?}", err);
+			return Err(err);
+		});
 
-		let analysis = try_else!(ffmpeg.analyze_silence().await, err, {
+		let analysis_start = Instant::now();
+		let mut analyzing = PhaseProgress::new(duration.as_secs_f32());
+		reporter.force_report(TaskPhase::Analyzing, &analyzing);
+		let analysis = ffmpeg.analyze_silence(duration, |stats| {
+			if analyzing.update(stats) {
+				reporter.report(TaskPhase::Analyzing, &analyzing);
+			}
+		});
+		let analysis = try_else!(analysis.await, err, {
 			tracing::info!("analyze silence error: {:?}", err);
 			return Err(err);
 		});
 
+		METRICS.analysis_seconds.observe(analysis_start.elapsed().as_secs_f64());
 		let playtime_after_conversion_s = analysis.audible.iter().map(|x| x.end - x.start).sum::<f32>();
 
 		tracing::debug!(
 			"total playtime: {}s; playtime after conversion: {}s; playtime reduced by {}%",
 			analysis.duration.as_secs_f32(),
 			playtime_after_conversion_s,
-			(1.0 - playtime_after_conversion_s / analysis.duration.as_secs_f32()) * 100.0
+			removed_percent(analysis.duration.as_secs_f32(), playtime_after_conversion_s)
 		);
 
 		let mut child = try_else!(ffmpeg.spawn_remove_silence(&analysis.audible).await, err, {
@@ -223,6 +458,9 @@ impl Task {
 		let stderr = child.stderr.take().unwrap();
 		let mut err_lines = BufReader::new(stderr).lines();
 		let mut error_log = Vec::new();
+		let encode_start = Instant::now();
+		let mut encoding = PhaseProgress::new(playtime_after_conversion_s);
+		reporter.force_report(TaskPhase::Encoding, &encoding);
 		// loop awaits on ffmpeg's stdout
 		loop {
 			// race reading an error and reading a line
@@ -262,44 +500,122 @@ impl Task {
 				continue;
 			};
 
-			let mut inner_lock = inner.write().await;
-
-			// output_time_ms is the same as _us bug in ffmpeg
-			if let TaskStatus::InProgress {
-				ref mut progress,
-				ref mut speed,
-			} = inner_lock.last_status
-			{
-				match StatsParse::parse_line(&line) {
-					StatsParse::Speed(new_speed) => {
-						*speed = new_speed;
-					}
-					StatsParse::Time(new_time) => {
-						let new_progress = new_time.as_secs_f32() / (playtime_after_conversion_s);
-						*progress = new_progress.min(1.0); // may overflow a little somtimes
-					}
-					_ => {
-						// nothing was updated
-						continue;
+			// the final status is always sent, throttling only drops intermediate progress
+			match StatsParse::parse_line(&line) {
+				// not measurable, the output is done once ffmpeg exits
+				StatsParse::End => reporter.force_report(TaskPhase::Finalizing, &PhaseProgress::new(0.0)),
+				stats => {
+					if encoding.update(stats) {
+						reporter.report(TaskPhase::Encoding, &encoding);
 					}
 				}
 			}
-			let last_status = inner_lock.last_status.clone();
-			Self::update_status(&mut inner_lock, task_id, last_status).await;
-			tracing::debug!("status: {:?}", inner_lock.last_status);
 		}
-
 		let status = child.wait().await.unwrap();
 		tracing::debug!("status: {:?} success: {}", status, status.success());
 
-		if status.success() {
-			Ok(())
-		} else {
-			Err(FFmpegError::FFmpeg(error_log.join("\n")))
+		if !status.success() {
+			return Err(FFmpegError::FFmpeg(error_log.join("\n")));
 		}
+		let encode_time = encode_start.elapsed().as_secs_f64();
+		METRICS.encode_seconds.observe(encode_time);
+		METRICS.encode_speed.observe(playtime_after_conversion_s as f64 / encode_time);
+
+		let original_duration = analysis.duration.as_secs_f32();
+		Ok(ConversionSummary {
+			original_duration,
+			output_duration: playtime_after_conversion_s,
+			removed_percent: removed_percent(original_duration, playtime_after_conversion_s),
+			cuts: analysis.cuts(),
+			output_size: tokio::fs::metadata(&output_file).await?.len(),
+			container: ffmpeg::OUTPUT_CONTAINER.to_string(),
+			video_codec: ffmpeg::OUTPUT_VIDEO_CODEC.to_string(),
+			audio_codec: ffmpeg::OUTPUT_AUDIO_CODEC.to_string(),
+			processing_time: reporter.started.elapsed().as_secs_f32(),
+		})
 
 		// if !error_log.is_empty() {
 		// 	tracing::info!("errors during task: {}", error_log.join("\n"));
 		// }
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn removed_percents() {
+		assert_eq!(removed_percent(100.0, 25.0), 75.0);
+		assert_eq!(removed_percent(100.0, 100.0), 0.0);
+		assert_eq!(removed_percent(0.0, 0.0), 0.0);
+	}
+
+	fn secs(x: f32) -> StatsParse {
+		StatsParse::Time(Duration::from_secs_f32(x))
+	}
+
+	#[test]
+	fn phase_progress() {
+		let start = Instant::now();
+		let mut progress = PhaseProgress::new(100.0);
+		// wall seconds since start, stats, whether it changed anything, then progress, speed and eta afterwards
+		let steps = [
+			// ffmpeg's speed until there are two time samples
+			(0.0, StatsParse::Speed(3.0), true, 0.0, 3.0, None),
+			(0.0, secs(10.0), true, 0.1, 3.0, None),
+			// 10 media seconds in 5 wall seconds
+			(5.0, secs(20.0), true, 0.2, 2.0, Some(40.0)),
+			// ignored once there's a smoothed speed
+			(5.0, StatsParse::Speed(9.0), false, 0.2, 2.0, Some(40.0)),
+			// time didn't move, measured from the last sample that moved
+			(6.0, secs(20.0), true, 0.2, 2.0, Some(40.0)),
+			// 1.6s per media second, averaged with 0.5s
+			(21.0, secs(30.0), true, 0.3, 1.0 / 1.05, Some(1.05 * 70.0)),
+			(21.0, StatsParse::Other, false, 0.3, 1.0 / 1.05, Some(1.05 * 70.0)),
+			// may go past the total
+			(31.0, secs(110.0), true, 1.0, 1.0 / ((0.5 + 1.6 + 0.125) / 3.0), Some(0.0)),
+		];
+		for (i, (wall, stats, changed, expected_progress, speed, eta)) in steps.into_iter().enumerate() {
+			assert_eq!(progress.update_at(stats, start + Duration::from_secs_f32(wall)), changed, "step {i}");
+			assert!((progress.progress - expected_progress).abs() < 1e-4, "step {i}: progress {}", progress.progress);
+			assert!((progress.speed - speed).abs() < 1e-3, "step {i}: speed {}", progress.speed);
+			match (progress.eta, eta) {
+				(Some(actual), Some(expected)) => {
+					assert!((actual.as_secs_f32() - expected).abs() < 1e-2, "step {i}: eta {actual:?}")
+				}
+				(actual, expected) => assert_eq!(actual.is_some(), expected.is_some(), "step {i}: eta {actual:?}"),
+			}
+		}
+	}
+
+	#[test]
+	fn reporter_throttles_unless_forced() {
+		let status_tx = watch::channel(VersionedStatus {
+			version: 0,
+			status: TaskStatus::Cancelled {
+				end_time: time::OffsetDateTime::now_utc(),
+			},
+			finished_at: None,
+		})
+		.0;
+		let mut reporter = ProgressReporter {
+			status_tx: &status_tx,
+			started: Instant::now(),
+			interval: Duration::from_secs(60 * 60),
+			last_published: None,
+		};
+		let progress = PhaseProgress::new(10.0);
+
+		let phase = || match status_tx.borrow().status {
+			TaskStatus::InProgress { phase, .. } => phase,
+			_ => panic!("not in progress"),
+		};
+		reporter.report(TaskPhase::Analyzing, &progress);
+		assert_eq!((status_tx.borrow().version, phase()), (1, TaskPhase::Analyzing));
+		reporter.report(TaskPhase::Analyzing, &progress);
+		assert_eq!(status_tx.borrow().version, 1);
+		reporter.force_report(TaskPhase::Encoding, &progress);
+		assert_eq!((status_tx.borrow().version, phase()), (2, TaskPhase::Encoding));
+	}
+}
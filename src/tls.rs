@@ -0,0 +1,68 @@
+use std::{
+	path::PathBuf,
+	time::{Duration, SystemTime},
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::config::CONFIG;
+
+/// Wait this long after a change for the other file to be replaced too
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+async fn certificate_paths() -> (PathBuf, PathBuf) {
+	let config_lock = CONFIG.read().await;
+	(config_lock.cert_pem_path.clone(), config_lock.key_pem_path.clone())
+}
+
+/// Modification times of the certificate and key files
+async fn modified() -> Option<(SystemTime, SystemTime)> {
+	let (cert_pem_path, key_pem_path) = certificate_paths().await;
+	let cert = tokio::fs::metadata(cert_pem_path).await.ok()?.modified().ok()?;
+	let key = tokio::fs::metadata(key_pem_path).await.ok()?.modified().ok()?;
+	Some((cert, key))
+}
+
+/// Reload the certificate and key from disk.
+///
+/// Established connections keep using the old certificate, new handshakes use the new one.
+/// On failure the old certificate stays in use.
+pub async fn reload_certificate(tls_config: &RustlsConfig) {
+	let (cert_pem_path, key_pem_path) = certificate_paths().await;
+	match tls_config.reload_from_pem_file(&cert_pem_path, &key_pem_path).await {
+		Ok(()) => tracing::info!("Reloaded certificate from {}", cert_pem_path.display()),
+		Err(err) => tracing::error!("Failed to reload certificate, keeping the old one: {}", err),
+	}
+}
+
+/// Reload the certificate when the files change, checking every `tls_reload_interval_seconds`.
+/// SIGHUP also reloads it, see `web::spawn_sighup_handler`.
+pub fn spawn_certificate_reloader(tls_config: RustlsConfig) {
+	tokio::task::spawn(async move {
+		let mut last_modified = modified().await;
+
+		loop {
+			let interval = CONFIG.read().await.tls_reload_interval_seconds;
+			if interval == 0 {
+				// check again later, in case polling is turned on by a config reload
+				tokio::time::sleep(Duration::from_secs(60)).await;
+				continue;
+			}
+			tokio::time::sleep(Duration::from_secs(interval)).await;
+
+			let current = modified().await;
+			// a missing file is likely in the middle of being replaced, wait for it
+			if current.is_some() && current != last_modified {
+				// the key and certificate are replaced one after the other,
+				// reloading in between would pair the new key with the old certificate
+				tokio::time::sleep(SETTLE_TIME).await;
+				if modified().await != current {
+					continue;
+				}
+				tracing::info!("Certificate files changed");
+				reload_certificate(&tls_config).await;
+				last_modified = current;
+			}
+		}
+	});
+}
@@ -1,20 +1,23 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::io;
 
 use std::net::SocketAddr;
+use std::os::unix::fs::MetadataExt;
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::body::{Bytes, StreamBody};
+use axum::body::{Body, Bytes, StreamBody};
 use axum::extract::multipart::MultipartRejection;
 use axum::extract::ws::rejection::WebSocketUpgradeRejection;
 use axum::extract::ws::{self, WebSocket};
-use axum::extract::{DefaultBodyLimit, Multipart, Path, Query, State, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, FromRef, Multipart, Path, Query, State, WebSocketUpgrade};
 use axum::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
 use axum::http::{HeaderMap, Request, StatusCode};
 use axum::middleware::Next;
-use axum::response::{IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::{debug_handler, middleware};
 use axum::{
 	routing::{get, post},
@@ -22,123 +25,576 @@ use axum::{
 };
 
 use axum_server::tls_rustls::RustlsConfig;
-use tokio::sync::{RwLock, RwLockReadGuard};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, Stream, StreamExt};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Notify, RwLock, RwLockReadGuard};
 use tokio_util::io::ReaderStream;
+use tokio_util::task::TaskTracker;
+use tower::ServiceExt;
 use tower_http::cors::{AllowHeaders, AllowOrigin};
 use tower_http::services::ServeDir;
 
-use crate::config::CONFIG;
-use crate::task::{Task, TaskId, TaskStatus, TaskUpdateMessage};
+use crate::acme::{self, AcmeChallenges};
+use crate::config::{BindAddress, Config, ConfigReloadResult, S3ServeMode, StorageConfig, CONFIG};
+use crate::metrics::METRICS;
+use crate::storage::Storage;
+use crate::{disk, listener, tls};
+use crate::ratelimit::{client_ip, rate_limit_middleware, RateLimiter, RateLimiters};
+use crate::task::{Task, TaskId, TaskStatus, TaskStatusReceiver, VersionedStatus};
+use crate::webhook::{self, CallbackUrlError};
 use crate::{config, task};
 
+mod admin;
+mod console;
+mod health;
+mod metrics;
+mod shutdown;
+mod tasks_ws;
+mod watch;
+
+use shutdown::Shutdown;
+
+/// The config values a new task needs, copied so the config isn't locked while it starts
+struct TaskSettings {
+	inputs_dir: std::path::PathBuf,
+	outputs_dir: std::path::PathBuf,
+	ffmpeg_executable: std::path::PathBuf,
+	progress_interval: Duration,
+	/// inputs that are deleted as soon as their task finishes aren't uploaded to storage
+	store_inputs: bool,
+}
+
+impl TaskSettings {
+	async fn current() -> Self {
+		let config_lock = CONFIG.read().await;
+		Self {
+			inputs_dir: config_lock.inputs_dir.clone(),
+			outputs_dir: config_lock.outputs_dir.clone(),
+			ffmpeg_executable: config_lock.ffmpeg_executable.clone(),
+			progress_interval: config_lock.progress_interval(),
+			store_inputs: config_lock.retention.inputs_minutes > 0,
+		}
+	}
+}
+
 struct TaskManager {
 	tasks: Arc<RwLock<HashMap<TaskId, task::Task>>>,
+	/// wakes the cleaner, so inputs can be deleted right away
+	task_finished: Arc<Notify>,
+	storage: Arc<Storage>,
+	/// webhook deliveries, shutdown waits for them
+	callbacks: TaskTracker,
+}
+
+/// How long finished tasks are kept, copied so the config isn't locked while tasks are
+#[derive(Clone, Copy)]
+struct Retention {
+	/// unless the task asked for `keep_hours`
+	completed: time::Duration,
+	failed: time::Duration,
+	inputs: time::Duration,
+}
+
+impl Retention {
+	fn new(config: &Config) -> Self {
+		Self {
+			completed: time::Duration::minutes(config.delete_files_after_minutes as i64),
+			failed: time::Duration::minutes(config.retention.failed_minutes as i64),
+			inputs: time::Duration::minutes(config.retention.inputs_minutes as i64),
+		}
+	}
+
+	async fn current() -> Self {
+		Self::new(&*CONFIG.read().await)
+	}
+
+	/// When a finished task and its files are removed
+	fn expires_at(&self, task: &Task, status: &TaskStatus) -> Option<time::OffsetDateTime> {
+		let finished_at = task.finished_at()?;
+		let retention = match status {
+			TaskStatus::Completed { .. } => task.keep_for().unwrap_or(self.completed),
+			_ => self.failed,
+		};
+		Some(finished_at + retention)
+	}
+}
+
+/// Bytes that have to be freed before `needed` more bytes of task files fit, and why
+async fn storage_shortfall(
+	storage: &StorageConfig,
+	inputs_dir: &std::path::Path,
+	outputs_dir: &std::path::Path,
+	needed: u64,
+) -> io::Result<Option<(u64, String)>> {
+	let mut shortfall = None::<(u64, String)>;
+	for dir in [inputs_dir, outputs_dir] {
+		let free = disk::free_space(dir)?;
+		let wanted = storage.min_free_space.saturating_add(needed);
+		if free < wanted && shortfall.as_ref().is_none_or(|x| wanted - free > x.0) {
+			shortfall = Some((wanted - free, format!("only {free} bytes free in {}", dir.display())));
+		}
+	}
+	if let Some(max_total_size) = storage.max_total_size {
+		let used = disk::dir_size(inputs_dir).await + disk::dir_size(outputs_dir).await;
+		let wanted = used.saturating_add(needed);
+		if wanted > max_total_size && shortfall.as_ref().is_none_or(|x| wanted - max_total_size > x.0) {
+			let problem = format!("storage quota of {max_total_size} bytes reached, {used} used");
+			shortfall = Some((wanted - max_total_size, problem));
+		}
+	}
+	Ok(shortfall)
 }
 
 impl TaskManager {
-	fn new() -> Self {
+	fn new(storage: Storage) -> Self {
 		Self {
 			tasks: Arc::new(RwLock::new(HashMap::new())),
+			task_finished: Arc::new(Notify::new()),
+			storage: Arc::new(storage),
+			callbacks: TaskTracker::new(),
 		}
 	}
 
-	async fn new_task(&self, input_data: impl AsRef<[u8]>) -> io::Result<TaskId> {
-		let config_lock = CONFIG.read().await;
+	async fn new_task(
+		&self,
+		input_data: impl AsRef<[u8]>,
+		submitter: String,
+		keep_for: Option<time::Duration>,
+	) -> io::Result<TaskId> {
+		// space was already made by `submit`, before reading the input
+		let settings = TaskSettings::current().await;
 
 		let task_id = Task::gen_id();
-		let task_id_string = task_id.to_string();
-
-		let input_file_path = config_lock.inputs_dir.join(&task_id_string);
+		let input_file_path = settings.inputs_dir.join(task_id.to_string());
 
 		tokio::fs::write(&input_file_path, input_data).await?;
 
-		let task =
-			Task::new(input_file_path, task_id, &config_lock.outputs_dir, config_lock.ffmpeg_executable.clone())?;
+		self.start_task(task_id, input_file_path, submitter, keep_for, settings).await
+	}
+
+	/// Like [`TaskManager::new_task`], but moves an existing file into the inputs dir instead
+	async fn new_task_from_file(&self, path: &std::path::Path, submitter: String) -> io::Result<TaskId> {
+		let settings = TaskSettings::current().await;
+		let metadata = tokio::fs::metadata(path).await?;
+		// on the same filesystem the input is only renamed, and just the output needs space
+		let same_filesystem =
+			tokio::fs::metadata(&settings.inputs_dir).await.is_ok_and(|x| x.dev() == metadata.dev());
+		self.ensure_space(if same_filesystem { metadata.len() } else { metadata.len() * 2 }).await?;
+		let task_id = Task::gen_id();
+		let input_file_path = settings.inputs_dir.join(task_id.to_string());
+
+		if tokio::fs::rename(path, &input_file_path).await.is_err() {
+			// different filesystems
+			tokio::fs::copy(path, &input_file_path).await?;
+			tokio::fs::remove_file(path).await?;
+		}
+
+		self.start_task(task_id, input_file_path, submitter, None, settings).await
+	}
+
+	async fn start_task(
+		&self,
+		task_id: TaskId,
+		input_file_path: std::path::PathBuf,
+		submitter: String,
+		keep_for: Option<time::Duration>,
+		settings: TaskSettings,
+	) -> io::Result<TaskId> {
+		let output_file_path = settings.outputs_dir.join(task_id.to_string());
+
+		let stored_input = settings.store_inputs.then(|| input_file_path.clone());
+		let task = Task::new(
+			input_file_path,
+			output_file_path.clone(),
+			task_id,
+			settings.ffmpeg_executable,
+			settings.progress_interval,
+			submitter,
+			keep_for,
+		)?;
+
+		let mut updates = task.subscribe();
+		let (task_finished, storage) = (self.task_finished.clone(), self.storage.clone());
+		tokio::task::spawn(async move {
+			// uploaded while encoding, so submit doesn't wait for it
+			let store_input = async {
+				let Some(input_file_path) = stored_input else {
+					return;
+				};
+				if let Err(err) = storage.store_input(task_id, &input_file_path).await {
+					tracing::error!("failed to store input of task {}: {}", task_id, err);
+				}
+			};
+			let finished = async {
+				updates
+					.wait_for(|x| x.finished_at.is_some())
+					.await
+					.is_ok_and(|x| matches!(x.status, TaskStatus::Completed { .. }))
+			};
+			let ((), completed) = tokio::join!(store_input, finished);
+			if completed {
+				if let Err(err) = storage.store_output(task_id, &output_file_path).await {
+					tracing::error!("failed to store output of task {}: {}", task_id, err);
+				}
+			}
+			task_finished.notify_one();
+		});
 
 		self.tasks.write().await.insert(task_id, task);
 
 		Ok(task_id)
 	}
 
-	async fn cleanup_tasks(&self) {
-		let config_lock = CONFIG.read().await;
+	/// Remove expired tasks, and inputs of finished tasks once they're past retention.
+	/// Returns when something expires next
+	async fn cleanup_tasks(&self) -> Option<time::OffsetDateTime> {
+		let (retention, inputs_dir) = {
+			let config_lock = CONFIG.read().await;
+			(Retention::new(&config_lock), config_lock.inputs_dir.clone())
+		};
 		let current_time = time::OffsetDateTime::now_utc();
+		let mut next_expiry: Option<time::OffsetDateTime> = None;
 		let mut keys_to_delete = Vec::new();
+		let mut stale_inputs = Vec::new();
+		let mut expired_outputs = Vec::new();
+
 		let mut tasks_lock = self.tasks.write().await;
 		for (id, task) in tasks_lock.iter() {
-			let status = task.last_status().await;
-			if let TaskStatus::Completed { end_time } = status {
-				let to_delete =
-					end_time + time::Duration::minutes(config_lock.delete_files_after_minutes as i64) < current_time;
-				if to_delete {
-					tracing::info!("deleting task {}", id);
-					keys_to_delete.push(*id);
+			let status = task.last_status();
+			let (Some(finished_at), Some(expires_at)) = (task.finished_at(), retention.expires_at(task, &status))
+			else {
+				continue;
+			};
+			if expires_at <= current_time {
+				tracing::info!("deleting task {}", id);
+				METRICS.cleanup_deletions.inc("task");
+				keys_to_delete.push(*id);
+				if let TaskStatus::Completed { .. } = status {
+					expired_outputs.push(*id);
 				}
+				continue;
+			}
+			next_expiry = Some(next_expiry.map_or(expires_at, |x| x.min(expires_at)));
+
+			let input_expires_at = finished_at + retention.inputs;
+			if input_expires_at <= current_time {
+				stale_inputs.push(*id);
+			} else {
+				next_expiry = Some(next_expiry.map_or(input_expires_at, |x| x.min(input_expires_at)));
 			}
 		}
 		tasks_lock.retain(|k, _| !keys_to_delete.contains(k));
+		drop(tasks_lock);
+
+		for id in expired_outputs {
+			self.delete_stored_output(id).await;
+		}
+		for id in stale_inputs {
+			// already gone after the first sweep
+			match self.storage.delete_input(id, &inputs_dir.join(id.to_string())).await {
+				Ok(()) => {
+					tracing::info!("deleted input of finished task {}", id);
+					METRICS.cleanup_deletions.inc("input_file");
+				}
+				Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+				Err(err) => tracing::warn!("failed to delete input of task {}: {}", id, err),
+			}
+		}
+		next_expiry
 	}
 
 	async fn cleanup_task_files(&self) {
-		let config_lock = CONFIG.read().await;
-		for dir_entry in config_lock.outputs_dir.read_dir().unwrap().flatten() {
+		let (inputs_dir, outputs_dir) = {
+			let config_lock = CONFIG.read().await;
+			(config_lock.inputs_dir.clone(), config_lock.outputs_dir.clone())
+		};
+		for dir_entry in outputs_dir.read_dir().unwrap().flatten() {
 			let file_name = dir_entry.file_name().to_string_lossy().to_string();
 			if let Ok(task_id) = file_name.parse::<TaskId>() {
 				if !self.tasks.read().await.contains_key(&task_id) {
 					tracing::info!("cleaning output file {}", task_id);
-					let _ = tokio::fs::remove_file(dir_entry.path()).await;
+					if tokio::fs::remove_file(dir_entry.path()).await.is_ok() {
+						METRICS.cleanup_deletions.inc("output_file");
+					}
 				}
 			}
 		}
-		for dir_entry in config_lock.inputs_dir.read_dir().unwrap().flatten() {
+		for dir_entry in inputs_dir.read_dir().unwrap().flatten() {
 			let file_name = dir_entry.file_name().to_string_lossy().to_string();
 			if let Ok(task_id) = file_name.parse::<TaskId>() {
 				if !self.tasks.read().await.contains_key(&task_id) {
 					tracing::info!("cleaning input file {}", task_id);
-					let _ = tokio::fs::remove_file(dir_entry.path()).await;
+					match self.storage.delete_input(task_id, &dir_entry.path()).await {
+						Ok(()) => METRICS.cleanup_deletions.inc("input_file"),
+						Err(err) => tracing::warn!("failed to delete input of task {}: {}", task_id, err),
+					}
+				}
+			}
+		}
+	}
+
+	/// Make room for `needed` more bytes of task files, evicting the oldest completed tasks
+	/// if that frees enough. Fails with [`io::ErrorKind::StorageFull`] otherwise
+	async fn ensure_space(&self, needed: u64) -> io::Result<()> {
+		let (storage, inputs_dir, outputs_dir) = {
+			let config_lock = CONFIG.read().await;
+			(config_lock.storage.clone(), config_lock.inputs_dir.clone(), config_lock.outputs_dir.clone())
+		};
+		let Some((shortfall, problem)) = storage_shortfall(&storage, &inputs_dir, &outputs_dir, needed).await? else {
+			return Ok(());
+		};
+
+		// decided up front, a disk filled by something else shouldn't cost every completed output
+		let mut evict = Vec::new();
+		let mut freed = 0;
+		for (task_id, size) in self.eviction_candidates(&inputs_dir, &outputs_dir).await {
+			if freed >= shortfall {
+				break;
+			}
+			freed += size;
+			evict.push(task_id);
+		}
+		if freed < shortfall {
+			tracing::warn!("not enough storage for {needed} bytes: {problem}");
+			return Err(io::Error::new(io::ErrorKind::StorageFull, problem));
+		}
+		for task_id in evict {
+			self.evict(task_id, &inputs_dir, &outputs_dir).await;
+		}
+
+		match storage_shortfall(&storage, &inputs_dir, &outputs_dir, needed).await? {
+			None => Ok(()),
+			Some((_, problem)) => {
+				tracing::warn!("not enough storage for {needed} bytes after evicting: {problem}");
+				Err(io::Error::new(io::ErrorKind::StorageFull, problem))
+			}
+		}
+	}
+
+	/// Completed tasks that may be evicted with the bytes their local files take, oldest first.
+	/// Tasks submitted with `keep_hours` are kept
+	async fn eviction_candidates(
+		&self,
+		inputs_dir: &std::path::Path,
+		outputs_dir: &std::path::Path,
+	) -> Vec<(TaskId, u64)> {
+		let mut candidates = self
+			.tasks
+			.read()
+			.await
+			.values()
+			.filter(|x| x.keep_for().is_none())
+			.filter_map(|x| match x.last_status() {
+				TaskStatus::Completed { end_time, .. } => Some((end_time, x.id)),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		candidates.sort();
+
+		let mut sized = Vec::with_capacity(candidates.len());
+		for (_, task_id) in candidates {
+			let mut size = 0;
+			for dir in [inputs_dir, outputs_dir] {
+				if let Ok(metadata) = tokio::fs::metadata(dir.join(task_id.to_string())).await {
+					size += metadata.len();
 				}
 			}
+			// e.g. outputs in s3, removing them doesn't help
+			if size > 0 {
+				sized.push((task_id, size));
+			}
+		}
+		sized
+	}
+
+	/// Remove a completed task and its files
+	async fn evict(&self, task_id: TaskId, inputs_dir: &std::path::Path, outputs_dir: &std::path::Path) {
+		if self.tasks.write().await.remove(&task_id).is_none() {
+			return;
+		}
+
+		tracing::info!("evicting task {} to free storage", task_id);
+		METRICS.cleanup_deletions.inc("evicted_task");
+		if let Err(err) = self.storage.delete_input(task_id, &inputs_dir.join(task_id.to_string())).await {
+			if err.kind() != io::ErrorKind::NotFound {
+				tracing::warn!("failed to delete input of task {}: {}", task_id, err);
+			}
+		}
+		let _ = tokio::fs::remove_file(outputs_dir.join(task_id.to_string())).await;
+		self.delete_stored_output(task_id).await;
+	}
+
+	async fn delete_stored_output(&self, task_id: TaskId) {
+		if let Err(err) = self.storage.delete_output(task_id).await {
+			tracing::warn!("failed to delete stored output of task {}: {}", task_id, err);
+		}
+	}
+
+	async fn tasks_in_progress(&self) -> Vec<TaskId> {
+		let tasks_lock = self.tasks.read().await;
+		tasks_lock.values().filter(|x| matches!(x.last_status(), TaskStatus::InProgress { .. })).map(|x| x.id).collect()
+	}
+
+	/// Wait until no task is in progress, including ones started while waiting
+	async fn wait_for_running_tasks(&self) {
+		loop {
+			let in_progress = self.tasks_in_progress().await;
+			if in_progress.is_empty() {
+				return;
+			}
+			futures_util::future::join_all(in_progress.into_iter().map(|x| self.wait_for_task(x))).await;
 		}
 	}
 
-	async fn get_task(&self, id: TaskId) -> Option<RwLockReadGuard<Task>> {
+	/// The task's final status, `None` if it was removed before finishing
+	async fn wait_for_task(&self, task_id: TaskId) -> Option<TaskStatus> {
+		// don't hold the task map lock while waiting
+		let mut updates = self.get_task(task_id).await?.subscribe();
+		let final_status = updates.wait_for(|x| !matches!(x.status, TaskStatus::InProgress { .. })).await.ok()?;
+		Some(final_status.status.clone())
+	}
+
+	async fn get_task(&self, id: TaskId) -> Option<RwLockReadGuard<'_, Task>> {
 		let a = self.tasks.read().await;
 		a.get(&id)?;
 		Some(RwLockReadGuard::map(a, |x| x.get(&id).unwrap()))
 	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, FromRef)]
 struct AppState {
 	task_manager: Arc<TaskManager>,
+	rate_limiters: RateLimiters,
+	acme_challenges: AcmeChallenges,
+	shutdown: Arc<Shutdown>,
 }
 
-fn spawn_task_cleaner(task_manager: Arc<TaskManager>) {
+fn spawn_task_cleaner(task_manager: Arc<TaskManager>, rate_limiters: RateLimiters) {
 	tokio::task::spawn(async move {
 		loop {
-			task_manager.cleanup_tasks().await;
+			let next_expiry = task_manager.cleanup_tasks().await;
 			task_manager.cleanup_task_files().await;
-			tokio::time::sleep(Duration::from_secs(60)).await;
+			rate_limiters.prune().await;
+
+			let sweep_interval = Duration::from_secs(CONFIG.read().await.retention.sweep_interval_seconds);
+			let until_expiry = next_expiry
+				.and_then(|x| Duration::try_from(x - time::OffsetDateTime::now_utc()).ok())
+				.unwrap_or(sweep_interval);
+			// at least a second, so tasks expiring together are handled in one sweep
+			let sleep = until_expiry.min(sweep_interval).max(Duration::from_secs(1));
+			tokio::select! {
+				_ = tokio::time::sleep(sleep) => {}
+				_ = task_manager.task_finished.notified() => {}
+			}
+		}
+	});
+}
+
+#[derive(serde::Serialize)]
+struct ConfigReloadReport {
+	changed: Vec<String>,
+	restart_required: Vec<String>,
+	warnings: Vec<String>,
+}
+
+/// Reload the config file and log what changed.
+/// On error returns the problems found, the current config stays in effect.
+async fn reload_config() -> Result<ConfigReloadReport, Vec<String>> {
+	let (changed, warnings) = match config::reload_config().await {
+		ConfigReloadResult::Ok { changed, warnings } => (changed, warnings),
+		ConfigReloadResult::Err(errors) => {
+			let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+			tracing::error!("Failed to reload config, keeping the current one:\n{}", errors.join("\n"));
+			return Err(errors);
+		}
+	};
+	let warnings = warnings.iter().map(ToString::to_string).collect::<Vec<_>>();
+	for warning in &warnings {
+		tracing::warn!("{}", warning);
+	}
+
+	let restart_required = changed.iter().filter(|x| config::requires_restart(x)).cloned().collect::<Vec<_>>();
+	if changed.is_empty() {
+		tracing::info!("Config reloaded, nothing changed");
+	} else {
+		tracing::info!("Config reloaded, changed: {}", changed.join(", "));
+	}
+	if !restart_required.is_empty() {
+		tracing::warn!("Restart to apply: {}", restart_required.join(", "));
+	}
+
+	Ok(ConfigReloadReport {
+		changed,
+		restart_required,
+		warnings,
+	})
+}
+
+/// Reload the config, and the certificate if serving tls, on SIGHUP
+fn spawn_sighup_handler(tls_config: Option<RustlsConfig>) {
+	tokio::task::spawn(async move {
+		let mut sighup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+		while sighup.recv().await.is_some() {
+			tracing::info!("SIGHUP received, reloading");
+			let _ = reload_config().await;
+			if let Some(tls_config) = &tls_config {
+				tls::reload_certificate(tls_config).await;
+			}
 		}
 	});
 }
 
 pub async fn initialize_server() {
 	let app_state: AppState = AppState {
-		task_manager: Arc::new(TaskManager::new()),
+		task_manager: Arc::new(TaskManager::new(Storage::from_config(&CONFIG.read().await.storage))),
+		rate_limiters: RateLimiters::new(),
+		acme_challenges: AcmeChallenges::default(),
+		shutdown: Arc::new(Shutdown::new()),
 	};
 
-	spawn_task_cleaner(app_state.task_manager.clone());
+	// before the cleaner runs, it would delete their outputs
+	shutdown::restore_tasks(&app_state.task_manager).await;
+	spawn_task_cleaner(app_state.task_manager.clone(), app_state.rate_limiters.clone());
+	console::spawn_console(app_state.task_manager.clone());
+	watch::spawn_watcher(app_state.task_manager.clone(), app_state.shutdown.clone());
+
+	let server_handle = axum_server::Handle::new();
+	tokio::task::spawn({
+		let (shutdown, task_manager, server_handle) =
+			(app_state.shutdown.clone(), app_state.task_manager.clone(), server_handle.clone());
+		async move {
+			shutdown::signal_received().await;
+			let grace_period = shutdown.begin(&task_manager).await;
+			server_handle.graceful_shutdown(Some(grace_period));
+		}
+	});
 
-	let router = Router::new()
-		.route("/submit", post(submit))
-		.route("/status", get(status))
-		.route("/status_ws", get(status_ws))
+	let limited = |limiter: &Arc<RateLimiter>| middleware::from_fn_with_state(limiter.clone(), rate_limit_middleware);
+
+	let acme_config = CONFIG.read().await.acme.clone();
+
+	let mut router = Router::new()
+		.route("/submit", post(submit).route_layer(limited(&app_state.rate_limiters.submit)))
+		.route("/status", get(status).route_layer(limited(&app_state.rate_limiters.status)))
+		.route("/status_ws", get(status_ws).route_layer(limited(&app_state.rate_limiters.websocket)))
+		.route("/tasks_ws", get(tasks_ws::tasks_ws).route_layer(limited(&app_state.rate_limiters.websocket)))
+		.route("/status_sse", get(status_sse).route_layer(limited(&app_state.rate_limiters.websocket)))
+		.route("/metrics", get(metrics::metrics))
+		.route("/healthz", get(health::healthz))
+		.route("/readyz", get(health::readyz))
 		.route("/videos/:video", get(videos))
-		.fallback_service(ServeDir::new(CONFIG.read().await.web_root.clone()))
-		.with_state(app_state)
+		.nest("/admin", admin::router());
+	if acme_config.enabled {
+		// otherwise challenges can still be placed in the web root by hand
+		router = router.route("/.well-known/acme-challenge/:token", get(acme::serve_challenge));
+	}
+	let router = router
+		.fallback(web_root)
+		.with_state(app_state.clone())
 		.layer(middleware::from_fn(meta_header_middleware))
-		.layer(DefaultBodyLimit::max(config::CONFIG.read().await.max_file_size as usize))
+		// enforced in `submit`, so changes to max_file_size apply without a restart
+		.layer(DefaultBodyLimit::disable())
 		.layer(
 			tower_http::cors::CorsLayer::permissive()
 				.allow_origin(AllowOrigin::mirror_request())
@@ -148,19 +604,92 @@ pub async fn initialize_server() {
 				.expose_headers([CONTENT_TYPE, CONTENT_LENGTH]),
 		);
 
-	let config_lock = config::CONFIG.read().await;
+	// clone what's needed, holding the lock while serving would block config reloads
+	let (bind_address, port, tls, http_redirect_port, cert_pem_path, key_pem_path) = {
+		let config_lock = config::CONFIG.read().await;
+		(
+			config_lock.bind_address.clone(),
+			config_lock.port,
+			config_lock.tls,
+			config_lock.http_redirect_port,
+			config_lock.cert_pem_path.clone(),
+			config_lock.key_pem_path.clone(),
+		)
+	};
 
-	let tls_config = RustlsConfig::from_pem_file(&config_lock.cert_pem_path, &config_lock.key_pem_path)
-		.await
-		.unwrap();
+	let make_service = router.clone().into_make_service_with_connect_info::<SocketAddr>();
 
-	let addr = SocketAddr::from(([0, 0, 0, 0], config_lock.port));
-	tracing::info!("Using tls");
-	tracing::debug!("Started server on {}", addr);
-	axum_server::bind_rustls(addr, tls_config)
-		.serve(router.into_make_service())
-		.await
-		.unwrap();
+	match bind_address {
+		BindAddress::Ip(ip) if tls => {
+			let tls_config = if acme_config.enabled && acme::certificate_validity_left(&cert_pem_path).is_none() {
+				tracing::info!("No certificate yet, serving a self-signed one until acme provides it");
+				let (cert, key) = acme::placeholder_certificate(&acme_config.domains).unwrap();
+				RustlsConfig::from_pem(cert, key).await.unwrap()
+			} else {
+				RustlsConfig::from_pem_file(&cert_pem_path, &key_pem_path).await.unwrap()
+			};
+
+			if let Some(redirect_port) = http_redirect_port {
+				listener::spawn_https_redirect(
+					SocketAddr::new(ip, redirect_port),
+					port,
+					app_state.acme_challenges.clone(),
+				);
+			}
+
+			if acme_config.enabled {
+				if let Some(redirect_port) = http_redirect_port.filter(|x| *x != 80) {
+					tracing::warn!(
+						"acme: the certificate authority connects on port 80, forward it to http_redirect_port {}",
+						redirect_port
+					);
+				}
+				acme::spawn_renewal(tls_config.clone(), app_state.acme_challenges.clone());
+			}
+			tls::spawn_certificate_reloader(tls_config.clone());
+			spawn_sighup_handler(Some(tls_config.clone()));
+
+			let addr = SocketAddr::new(ip, port);
+			tracing::info!("Using tls");
+			tracing::debug!("Started server on {}", addr);
+			axum_server::bind_rustls(addr, tls_config).handle(server_handle).serve(make_service).await.unwrap();
+		}
+		BindAddress::Ip(ip) => {
+			spawn_sighup_handler(None);
+			let addr = SocketAddr::new(ip, port);
+			tracing::info!("Using plain http");
+			tracing::debug!("Started server on {}", addr);
+			axum_server::bind(addr).handle(server_handle).serve(make_service).await.unwrap();
+		}
+		BindAddress::Unix(path) => {
+			// checked on startup
+			assert!(!tls, "tls is not supported on unix sockets");
+			spawn_sighup_handler(None);
+			let accept = listener::UnixAccept::bind(&path)
+				.unwrap_or_else(|err| panic!("failed to bind to unix:{}: {err}", path.display()));
+			tracing::info!("Using plain http");
+			tracing::debug!("Started server on unix:{}", path.display());
+			let server = axum::Server::builder(accept)
+				.serve(router.into_make_service())
+				.with_graceful_shutdown(app_state.shutdown.stopping());
+			tokio::select! {
+				result = server => result.unwrap(),
+				_ = app_state.shutdown.grace_period_over() => tracing::info!("closing remaining connections"),
+			}
+		}
+	}
+
+	shutdown::finish(&app_state.task_manager).await;
+	tracing::info!("Server stopped");
+}
+
+/// Serve static files from the currently configured web root
+async fn web_root(request: Request<Body>) -> Response {
+	let web_root = CONFIG.read().await.web_root.clone();
+	match ServeDir::new(web_root).oneshot(request).await {
+		Ok(response) => response.into_response(),
+		Err(infallible) => match infallible {},
+	}
 }
 
 async fn meta_header_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
@@ -174,6 +703,7 @@ async fn meta_header_middleware<B>(request: Request<B>, next: Next<B>) -> Respon
 enum EndpointResult<T: IntoResponse> {
 	Ok(T),
 	Accepted(T),
+	NoContent,
 	Err(StatusCode, Option<Cow<'static, str>>),
 }
 
@@ -182,24 +712,37 @@ impl<T: IntoResponse> IntoResponse for EndpointResult<T> {
 		match self {
 			Self::Ok(t) => t.into_response(),
 			Self::Accepted(r) => (StatusCode::ACCEPTED, r).into_response(),
+			Self::NoContent => StatusCode::NO_CONTENT.into_response(),
 			Self::Err(code, msg) => (code, msg.unwrap_or_default()).into_response(),
 		}
 	}
 }
 
-async fn parse_multipart<'a>(multipart: &mut Multipart) -> Result<Bytes, Cow<'a, str>> {
-	while let Some(a) = multipart.next_field().await.ok().flatten() {
+async fn parse_multipart(
+	multipart: &mut Multipart,
+	max_file_size: u64,
+) -> Result<Bytes, (StatusCode, Cow<'static, str>)> {
+	while let Some(mut a) = multipart.next_field().await.ok().flatten() {
 		let Some(name) = a.name() else {
-			return Err("No name for field".into());
+			return Err((StatusCode::BAD_REQUEST, "No name for field".into()));
 		};
 		if name == "file" {
 			let is_good_mime = a.content_type().map(|x| x.starts_with("video/")).unwrap_or(false);
 			if is_good_mime {
-				return a.bytes().await.map_err(|_| "Failed to read body".into());
+				let mut data = Vec::new();
+				while let Some(chunk) =
+					a.chunk().await.map_err(|_| (StatusCode::BAD_REQUEST, Cow::from("Failed to read body")))?
+				{
+					if (data.len() + chunk.len()) as u64 > max_file_size {
+						return Err((StatusCode::PAYLOAD_TOO_LARGE, "File too large".into()));
+					}
+					data.extend_from_slice(&chunk);
+				}
+				return Ok(data.into());
 			}
 		}
 	}
-	Err("No file field".into())
+	Err((StatusCode::BAD_REQUEST, "No file field".into()))
 }
 
 async fn drain_multipart(mut multipart: Multipart) {
@@ -211,19 +754,88 @@ async fn drain_multipart(mut multipart: Multipart) {
 	}
 }
 
+#[derive(serde::Deserialize)]
+struct SubmitQuery {
+	/// POSTed to when the task finishes, see [`webhook::deliver`]
+	callback: Option<String>,
+	/// keep the output this long after completing instead of `delete_files_after_minutes`
+	keep_hours: Option<u64>,
+}
+
 /// Submit a video file to be encoded
 /// Accepts a `multipart/form-data` request with a `file` field
+/// and optional `callback` and `keep_hours` query parameters
 ///
 /// Returns the id of the encoding task, which the client may later query
 /// or an error along with an explanation message if the request is malformed.
 #[debug_handler]
-async fn submit(state: State<AppState>, multipart: Result<Multipart, MultipartRejection>) -> EndpointResult<String> {
+async fn submit(
+	state: State<AppState>,
+	Query(query): Query<SubmitQuery>,
+	peer: Option<ConnectInfo<SocketAddr>>,
+	headers: HeaderMap,
+	multipart: Result<Multipart, MultipartRejection>,
+) -> EndpointResult<String> {
 	tracing::debug!("submit {:?}", multipart.as_ref().map(|_| ()));
+
+	if state.shutdown.is_draining() {
+		METRICS.uploads_rejected.inc("draining");
+		return EndpointResult::Err(
+			StatusCode::SERVICE_UNAVAILABLE,
+			Some("Not accepting new videos right now, try again later".into()),
+		);
+	}
+
+	let callback = match query.callback {
+		Some(url) => match webhook::check_callback_url(&url).await {
+			Ok(x) => Some(x),
+			Err(err @ CallbackUrlError::Invalid(_)) => {
+				METRICS.uploads_rejected.inc("invalid_callback");
+				return EndpointResult::Err(StatusCode::BAD_REQUEST, Some(err.to_string().into()));
+			}
+			Err(err @ CallbackUrlError::NotAllowed) => {
+				METRICS.uploads_rejected.inc("callback_not_allowed");
+				return EndpointResult::Err(StatusCode::FORBIDDEN, Some(err.to_string().into()));
+			}
+		},
+		None => None,
+	};
+
+	let max_keep_hours = CONFIG.read().await.retention.max_keep_hours;
+	if query.keep_hours.is_some_and(|x| x > max_keep_hours) {
+		METRICS.uploads_rejected.inc("bad_request");
+		return EndpointResult::Err(
+			StatusCode::BAD_REQUEST,
+			Some(format!("keep_hours may be at most {max_keep_hours}").into()),
+		);
+	}
+	let keep_for = query.keep_hours.map(|x| time::Duration::hours(x as i64));
+
+	// reject early instead of reading the whole body
+	let content_length = headers.get(CONTENT_LENGTH).and_then(|x| x.to_str().ok()).and_then(|x| x.parse::<u64>().ok());
+	let (max_file_size, submitter) = {
+		let config_lock = CONFIG.read().await;
+		let trusted_proxy_header = config_lock.rate_limit.trusted_proxy_header.as_deref();
+		(config_lock.max_file_size, client_ip(&headers, peer.map(|x| x.0), trusted_proxy_header).to_string())
+	};
+	if content_length.is_some_and(|x| x > max_file_size) {
+		METRICS.uploads_rejected.inc("too_large");
+		return EndpointResult::Err(StatusCode::PAYLOAD_TOO_LARGE, Some("File too large".into()));
+	}
+	// the output is assumed to be about as large as the input
+	if let Err(err) = state.task_manager.ensure_space(content_length.unwrap_or(max_file_size) * 2).await {
+		return insufficient_storage(err);
+	}
+
 	match multipart {
 		Ok(mut multipart) => {
-			let input_data = match parse_multipart(&mut multipart).await {
+			let input_data = match parse_multipart(&mut multipart, max_file_size).await {
 				Ok(x) => x,
-				Err(msg) => return EndpointResult::Err(StatusCode::BAD_REQUEST, Some(msg)),
+				Err((code, msg)) => {
+					let reason = if code == StatusCode::PAYLOAD_TOO_LARGE { "too_large" } else { "bad_request" };
+					METRICS.uploads_rejected.inc(reason);
+					return EndpointResult::Err(code, Some(msg));
+				}
 			};
 
 			// drain the request so it's possible to send a response
@@ -231,22 +843,54 @@ async fn submit(state: State<AppState>, multipart: Result<Multipart, MultipartRe
 			drain_multipart(multipart).await;
 
 			tracing::debug!("Length of file is {} bytes", input_data.len());
+			let input_size = input_data.len() as u64;
 
-			let task_id = match state.task_manager.new_task(input_data).await {
+			let task_id = match state.task_manager.new_task(input_data, submitter, keep_for).await {
 				Ok(task_id) => task_id,
+				Err(err) if err.kind() == io::ErrorKind::StorageFull => return insufficient_storage(err),
 				Err(err) => {
+					METRICS.uploads_rejected.inc("internal_error");
 					let err_string = err.to_string();
 					tracing::error!("Failed to start task: {}", err_string);
 					return EndpointResult::Err(StatusCode::INTERNAL_SERVER_ERROR, Some(err_string.into()));
 				}
 			};
 
+			METRICS.uploads_accepted.inc();
+			METRICS.upload_bytes.add(input_size);
+
+			if let Some(callback) = callback {
+				let task_manager = state.task_manager.clone();
+				state.task_manager.callbacks.spawn(async move {
+					if let Some(status) = task_manager.wait_for_task(task_id).await {
+						webhook::deliver(callback, task_id, status).await;
+					}
+				});
+			}
+
 			EndpointResult::Accepted(task_id.to_string())
 		}
-		Err(err) => EndpointResult::Err(StatusCode::BAD_REQUEST, Some(err.to_string().into())),
+		Err(err) => {
+			METRICS.uploads_rejected.inc("bad_request");
+			EndpointResult::Err(StatusCode::BAD_REQUEST, Some(err.to_string().into()))
+		}
 	}
 }
 
+fn insufficient_storage(err: io::Error) -> EndpointResult<String> {
+	METRICS.uploads_rejected.inc("insufficient_storage");
+	EndpointResult::Err(StatusCode::INSUFFICIENT_STORAGE, Some(format!("Not enough storage: {err}").into()))
+}
+
+#[derive(serde::Serialize)]
+struct StatusResponse {
+	#[serde(flatten)]
+	status: TaskStatus,
+	/// unix timestamp of when the task and its output are deleted, `null` until it finishes
+	#[serde(with = "time::serde::timestamp::option")]
+	expires_at: Option<time::OffsetDateTime>,
+}
+
 #[derive(serde::Deserialize)]
 struct TaskStatusQuery {
 	t: TaskId,
@@ -256,11 +900,77 @@ async fn status(
 	state: State<AppState>,
 	Query(TaskStatusQuery { t }): Query<TaskStatusQuery>,
 ) -> EndpointResult<String> {
-	let Some(status) = state.task_manager.get_task(t).await else {
+	let Some(task) = state.task_manager.get_task(t).await else {
 		return EndpointResult::Err(StatusCode::NOT_FOUND, Some("task not found".into()));
 	};
 
-	EndpointResult::Ok(serde_json::to_string(&status.last_status().await).unwrap())
+	let retention = Retention::current().await;
+	let status = task.last_status();
+	let expires_at = retention.expires_at(&task, &status);
+	EndpointResult::Ok(serde_json::to_string(&StatusResponse { status, expires_at }).unwrap())
+}
+
+struct SseState {
+	/// sent before anything from `rx`
+	first: Option<VersionedStatus>,
+	rx: TaskStatusReceiver,
+	finished: bool,
+	shutdown: Arc<Shutdown>,
+}
+
+/// Stream the task's status as server-sent events, for clients which can't use websockets.
+///
+/// Sends the current status first, unless `Last-Event-ID` shows the client already has it,
+/// and ends the stream after the task stops or when the server shuts down.
+/// A client that already has the final status gets 204, which stops `EventSource` from reconnecting.
+async fn status_sse(
+	state: State<AppState>,
+	Query(TaskStatusQuery { t }): Query<TaskStatusQuery>,
+	headers: HeaderMap,
+) -> EndpointResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+	let mut rx = match state.task_manager.get_task(t).await {
+		Some(task) => task.subscribe(),
+		None => return EndpointResult::Err(StatusCode::NOT_FOUND, Some("task not found".into())),
+	};
+	let first = rx.borrow_and_update().clone();
+
+	// event ids are status versions
+	let last_event_id = headers.get("Last-Event-ID").and_then(|x| x.to_str().ok()).and_then(|x| x.parse::<u64>().ok());
+	let up_to_date = last_event_id == Some(first.version);
+	if up_to_date && !matches!(first.status, TaskStatus::InProgress { .. }) {
+		return EndpointResult::NoContent;
+	}
+	let state = SseState {
+		finished: false,
+		first: (!up_to_date).then_some(first),
+		rx,
+		shutdown: state.shutdown.clone(),
+	};
+
+	let stream = futures_util::stream::unfold(state, |mut state| async move {
+		if state.finished {
+			return None;
+		}
+		let update = match state.first.take() {
+			Some(x) => x,
+			None => {
+				tokio::select! {
+					// a final status sent right before shutdown still goes out
+					biased;
+					// the task was removed
+					changed = state.rx.changed() => changed.ok()?,
+					_ = state.shutdown.stopping() => return None,
+				}
+				state.rx.borrow_and_update().clone()
+			}
+		};
+		state.finished = !matches!(update.status, TaskStatus::InProgress { .. });
+
+		let event = Event::default().id(update.version.to_string()).json_data(&update.status).unwrap();
+		Some((Ok(event), state))
+	});
+
+	EndpointResult::Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
 #[debug_handler]
@@ -279,7 +989,11 @@ async fn status_ws(
 
 	// reject upgrade if no task found
 	let (first_status, rx) = match state.task_manager.get_task(t).await {
-		Some(task) => (task.last_status().await, task.subscribe().await),
+		Some(task) => {
+			let mut rx = task.subscribe();
+			let first_status = rx.borrow_and_update().status.clone();
+			(first_status, rx)
+		}
 		None => {
 			tracing::info!("ws upgrade rejected: task not found");
 			return EndpointResult::Err(StatusCode::NOT_FOUND, None);
@@ -288,63 +1002,131 @@ async fn status_ws(
 
 	EndpointResult::Ok(
 		ws.on_failed_upgrade(|_| tracing::info!("ws upgrade failed"))
-			.on_upgrade(move |ws| ws_handler(ws, t, first_status, rx)),
+			.on_upgrade(move |ws| ws_handler(ws, t, first_status, rx, state.shutdown.clone())),
+	)
+}
+
+/// Close codes sent by the status websockets
+mod close_code {
+	pub const COMPLETED: u16 = 1000;
+	pub const ERROR: u16 = 4000;
+	pub const CANCELLED: u16 = 4001;
+	/// no frames from the client, not even pongs, for `websocket.idle_timeout_seconds`
+	pub const IDLE_TIMEOUT: u16 = 4002;
+	/// the task was removed, e.g. by the cleaner. 1001 would mean the server is going away
+	pub const TASK_REMOVED: u16 = 4003;
+	/// "service restart", the client may reconnect in a bit
+	pub const SHUTTING_DOWN: u16 = 1012;
+}
+
+/// Close code and reason for a task that stopped
+fn final_close_frame(status: &TaskStatus) -> Option<(u16, &'static str)> {
+	match status {
+		TaskStatus::InProgress { .. } => None,
+		TaskStatus::Completed { .. } => Some((close_code::COMPLETED, "completed")),
+		TaskStatus::Error(_) => Some((close_code::ERROR, "error")),
+		TaskStatus::Cancelled { .. } => Some((close_code::CANCELLED, "cancelled")),
+	}
+}
+
+/// Ping interval and idle timeout of status websockets
+async fn heartbeat_config() -> (Duration, Duration) {
+	let config_lock = CONFIG.read().await;
+	(
+		Duration::from_secs(config_lock.websocket.ping_interval_seconds),
+		Duration::from_secs(config_lock.websocket.idle_timeout_seconds),
 	)
 }
 
+/// Send a close frame and wait a bit for the client to acknowledge it
+async fn close_ws(
+	mut sender: SplitSink<WebSocket, ws::Message>,
+	mut receiver: SplitStream<WebSocket>,
+	code: u16,
+	reason: &'static str,
+) {
+	let frame = ws::CloseFrame {
+		code,
+		reason: reason.into(),
+	};
+	if sender.send(ws::Message::Close(Some(frame))).await.is_err() {
+		return;
+	}
+	let _ = tokio::time::timeout(Duration::from_secs(5), async {
+		while let Some(Ok(message)) = receiver.next().await {
+			if matches!(message, ws::Message::Close(_)) {
+				break;
+			}
+		}
+	})
+	.await;
+}
+
 async fn ws_handler(
-	mut ws: WebSocket,
+	ws: WebSocket,
 	target_task: TaskId,
 	first_status: TaskStatus,
-	mut task_rx: tokio::sync::broadcast::Receiver<TaskUpdateMessage>,
+	mut task_rx: TaskStatusReceiver,
+	shutdown: Arc<Shutdown>,
 ) {
 	tracing::info!("ws connected");
-	if ws.send(ws::Message::Ping(Vec::new())).await.is_err() {
-		tracing::info!("can't ping ws");
-		return;
-	}
+	let _active = METRICS.active_websockets.track();
+	let (mut sender, mut receiver) = ws.split();
 
 	// send first status because the socked might
 	// subscribe to channel after the task stops
 	// and it will never receive the error
-	let _ = ws
-		.send(ws::Message::Text(serde_json::to_string(&first_status).unwrap()))
-		.await;
+	if sender.send(ws::Message::Text(serde_json::to_string(&first_status).unwrap())).await.is_err() {
+		return;
+	}
+	if let Some((code, reason)) = final_close_frame(&first_status) {
+		close_ws(sender, receiver, code, reason).await;
+		return;
+	}
 
-	tokio::spawn(async move {
-		loop {
-			let rcv = task_rx.recv().await;
-			match rcv {
-				Ok(msg) => {
-					if let Err(x) = ws.send(ws::Message::Text(serde_json::to_string(&msg.1).unwrap())).await {
-						let x = x.into_inner();
-						if matches!(
-							x.downcast_ref::<io::Error>().map(|x| x.kind()),
-							Some(io::ErrorKind::ConnectionAborted)
-						) {
-							tracing::info!("failed to send ws message for {target_task}: ws closed {x:?}");
-							break;
-						} else {
-							tracing::info!("failed to send ws message for {target_task}: {x:?}");
-						}
-					};
-
-					if matches!(msg.1, TaskStatus::Error(_) | TaskStatus::Completed { .. }) {
-						// give axum time to flush the socket
-						tokio::time::sleep(Duration::from_millis(500)).await;
-						let _ = ws.close().await;
-						break;
-					}
+	let (ping_interval, idle_timeout) = heartbeat_config().await;
+	let mut ping = tokio::time::interval(ping_interval);
+	let mut last_seen = tokio::time::Instant::now();
+
+	let (code, reason) = loop {
+		tokio::select! {
+			// updates first and the shutdown last, so the final status isn't lost to a shutdown right after
+			biased;
+			changed = task_rx.changed() => {
+				if changed.is_err() {
+					break (close_code::TASK_REMOVED, "task removed");
+				}
+				let status = task_rx.borrow_and_update().status.clone();
+				if sender.send(ws::Message::Text(serde_json::to_string(&status).unwrap())).await.is_err() {
+					tracing::info!("failed to send ws message for {target_task}, closing");
+					return;
+				}
+				if let Some(close) = final_close_frame(&status) {
+					break close;
 				}
-				Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-					tracing::warn!("tx closed for {target_task}");
+			},
+			message = receiver.next() => match message {
+				// the client doesn't send anything meaningful, but any frame shows it's alive
+				Some(Ok(ws::Message::Close(_))) | Some(Err(_)) | None => {
+					tracing::info!("ws for {target_task} closed by client");
+					return;
 				}
-				Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-					tracing::warn!("rx lagged by {n} for {target_task}");
+				Some(Ok(_)) => last_seen = tokio::time::Instant::now(),
+			},
+			_ = ping.tick() => {
+				if last_seen.elapsed() > idle_timeout {
+					break (close_code::IDLE_TIMEOUT, "idle timeout");
+				}
+				if sender.send(ws::Message::Ping(Vec::new())).await.is_err() {
+					return;
 				}
 			}
+			_ = shutdown.stopping() => break (close_code::SHUTTING_DOWN, "server shutting down"),
 		}
-	});
+	};
+
+	tracing::info!("closing ws for {target_task}: {reason}");
+	close_ws(sender, receiver, code, reason).await;
 }
 
 #[derive(serde::Deserialize)]
@@ -352,29 +1134,103 @@ struct VideoDlQuery {
 	dl: u32,
 }
 
+/// Serve a task's output, from `outputs_dir` while it's there and from the storage backend after
 async fn videos(
 	state: State<AppState>,
 	Path(task_id): Path<TaskId>,
 	query: Option<Query<VideoDlQuery>>,
-) -> EndpointResult<(HeaderMap, StreamBody<ReaderStream<tokio::fs::File>>)> {
+) -> EndpointResult<Response> {
 	if let Some(task) = state.task_manager.get_task(task_id).await {
-		if matches!(task.last_status().await, TaskStatus::InProgress { .. }) {
+		// a cancelled task's output is incomplete
+		if matches!(task.last_status(), TaskStatus::InProgress { .. } | TaskStatus::Cancelled { .. }) {
 			return EndpointResult::Err(StatusCode::NOT_FOUND, Some("video not found".into()));
 		}
 	}
-
-	let file_path = CONFIG.read().await.outputs_dir.join(task_id.to_string());
-	let Ok(file_handle) = tokio::fs::File::open(file_path).await else {
-		// usually
-		return EndpointResult::Err(StatusCode::NOT_FOUND, Some("video not found".into()));
-	};
+	let download = matches!(query, Some(Query(VideoDlQuery { dl: 1 })));
 
 	let mut headers = HeaderMap::new();
 	headers.append(CONTENT_TYPE, "video/mp4".parse().unwrap());
-	headers.append(CONTENT_LENGTH, file_handle.metadata().await.unwrap().len().into());
-	if matches!(query, Some(Query(VideoDlQuery { dl: 1 }))) {
+	if download {
 		headers.append(CONTENT_DISPOSITION, "attachment".parse().unwrap());
 	}
 
-	EndpointResult::Ok((headers, StreamBody::new(ReaderStream::new(file_handle))))
+	let (file_path, serve_mode) = {
+		let config_lock = CONFIG.read().await;
+		(config_lock.outputs_dir.join(task_id.to_string()), config_lock.storage.s3.serve)
+	};
+	if let Ok(file_handle) = tokio::fs::File::open(file_path).await {
+		headers.append(CONTENT_LENGTH, file_handle.metadata().await.unwrap().len().into());
+		return EndpointResult::Ok((headers, StreamBody::new(ReaderStream::new(file_handle))).into_response());
+	}
+
+	let Some((s3, key)) = state.task_manager.storage.s3_output(task_id) else {
+		return EndpointResult::Err(StatusCode::NOT_FOUND, Some("video not found".into()));
+	};
+	match serve_mode {
+		S3ServeMode::Redirect => {
+			let url = s3.presigned_url(&key, download.then_some("attachment"));
+			EndpointResult::Ok(Redirect::temporary(url.as_str()).into_response())
+		}
+		S3ServeMode::Proxy => match s3.get(&key).await {
+			Ok(response) => {
+				if let Some(length) = response.content_length() {
+					headers.append(CONTENT_LENGTH, length.into());
+				}
+				EndpointResult::Ok((headers, StreamBody::new(response.bytes_stream())).into_response())
+			}
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {
+				EndpointResult::Err(StatusCode::NOT_FOUND, Some("video not found".into()))
+			}
+			Err(err) => {
+				tracing::error!("failed to get output of task {} from s3: {}", task_id, err);
+				EndpointResult::Err(StatusCode::BAD_GATEWAY, None)
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::task::ConversionSummary;
+
+	fn state_with_completed_task(task_id: TaskId) -> AppState {
+		let task_manager = TaskManager::new(Storage::Local);
+		let now = time::OffsetDateTime::now_utc();
+		let summary = ConversionSummary {
+			original_duration: 10.0,
+			output_duration: 6.0,
+			removed_percent: 40.0,
+			cuts: 2,
+			output_size: 5,
+			container: "mp4".to_string(),
+			video_codec: "h264".to_string(),
+			audio_codec: "aac".to_string(),
+			processing_time: 1.0,
+		};
+		let task = Task::restored(task_id, "test".to_string(), now, None, now, summary);
+		task_manager.tasks.try_write().unwrap().insert(task_id, task);
+		AppState {
+			task_manager: Arc::new(task_manager),
+			rate_limiters: RateLimiters::new(),
+			acme_challenges: AcmeChallenges::default(),
+			shutdown: Arc::new(Shutdown::new()),
+		}
+	}
+
+	async fn sse_status_code(state: AppState, last_event_id: Option<&str>) -> StatusCode {
+		let mut headers = HeaderMap::new();
+		if let Some(id) = last_event_id {
+			headers.insert("Last-Event-ID", id.parse().unwrap());
+		}
+		status_sse(State(state), Query(TaskStatusQuery { t: 1 }), headers).await.into_response().status()
+	}
+
+	#[tokio::test]
+	async fn sse_for_finished_task() {
+		// restored tasks are at version 0
+		assert_eq!(sse_status_code(state_with_completed_task(1), None).await, StatusCode::OK);
+		assert_eq!(sse_status_code(state_with_completed_task(1), Some("0")).await, StatusCode::NO_CONTENT);
+		assert_eq!(sse_status_code(state_with_completed_task(2), Some("0")).await, StatusCode::NOT_FOUND);
+	}
 }
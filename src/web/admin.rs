@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use super::{reload_config, AppState, EndpointResult, Retention, Shutdown, TaskManager};
+use crate::config::CONFIG;
+use crate::task::TaskStatus;
+
+/// page size when none is given
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Routes under `/admin`, only reachable with `Authorization: Bearer <admin_token>`
+pub(super) fn router() -> Router<AppState> {
+	Router::new()
+		.route("/reload_config", post(reload_config_endpoint))
+		.route("/tasks", get(list_tasks))
+		.route("/drain", get(drain_status).post(start_draining).delete(stop_draining))
+		.route_layer(middleware::from_fn(admin_auth_middleware))
+}
+
+/// compare without returning early, so the token can't be guessed by timing
+pub(super) fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn admin_auth_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+	let authorized = {
+		let config_lock = CONFIG.read().await;
+		let Some(admin_token) = config_lock.admin_token.as_deref() else {
+			// admin api is disabled without a token
+			return StatusCode::NOT_FOUND.into_response();
+		};
+		request
+			.headers()
+			.get(AUTHORIZATION)
+			.and_then(|x| x.to_str().ok())
+			.and_then(|x| x.strip_prefix("Bearer "))
+			.is_some_and(|x| tokens_match(x.as_bytes(), admin_token.as_bytes()))
+	};
+
+	if !authorized {
+		return (StatusCode::UNAUTHORIZED, "invalid admin token").into_response();
+	}
+	next.run(request).await
+}
+
+async fn reload_config_endpoint() -> EndpointResult<Response> {
+	match reload_config().await {
+		Ok(report) => EndpointResult::Ok(Json(report).into_response()),
+		Err(errors) => EndpointResult::Err(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join("\n").into())),
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum StatusFilter {
+	InProgress,
+	Completed,
+	Error,
+	Cancelled,
+}
+
+impl StatusFilter {
+	fn matches(self, status: &TaskStatus) -> bool {
+		matches!(
+			(self, status),
+			(Self::InProgress, TaskStatus::InProgress { .. })
+				| (Self::Completed, TaskStatus::Completed { .. })
+				| (Self::Error, TaskStatus::Error(_))
+				| (Self::Cancelled, TaskStatus::Cancelled { .. })
+		)
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SortKey {
+	#[default]
+	StartTime,
+	/// tasks without an end time, unfinished or failed, sort as the earliest
+	EndTime,
+	Submitter,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum SortOrder {
+	Asc,
+	#[default]
+	Desc,
+}
+
+/// Times are unix timestamps in seconds
+#[derive(Debug, serde::Deserialize)]
+struct TaskListQuery {
+	status: Option<StatusFilter>,
+	submitter: Option<String>,
+	started_after: Option<i64>,
+	started_before: Option<i64>,
+	#[serde(default)]
+	sort: SortKey,
+	#[serde(default)]
+	order: SortOrder,
+	#[serde(default)]
+	offset: usize,
+	limit: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+struct TaskEntry {
+	/// string, since ids don't fit into a javascript number
+	id: String,
+	submitter: String,
+	#[serde(with = "time::serde::timestamp")]
+	start_time: time::OffsetDateTime,
+	status: TaskStatus,
+	#[serde(with = "time::serde::timestamp::option")]
+	expires_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(serde::Serialize)]
+struct TaskList {
+	/// matching tasks, before pagination
+	total: usize,
+	offset: usize,
+	limit: usize,
+	tasks: Vec<TaskEntry>,
+}
+
+/// Tasks known to the server, including finished ones until they're cleaned up
+async fn list_tasks(
+	State(task_manager): State<Arc<TaskManager>>,
+	Query(query): Query<TaskListQuery>,
+) -> Json<TaskList> {
+	let retention = Retention::current().await;
+	let mut tasks = task_manager
+		.tasks
+		.read()
+		.await
+		.values()
+		.map(|task| {
+			let status = task.last_status();
+			TaskEntry {
+				id: task.id.to_string(),
+				submitter: task.submitter().to_string(),
+				start_time: task.start_time(),
+				expires_at: retention.expires_at(task, &status),
+				status,
+			}
+		})
+		.filter(|task| {
+			let start = task.start_time.unix_timestamp();
+			query.status.is_none_or(|x| x.matches(&task.status))
+				&& query.submitter.as_ref().is_none_or(|x| *x == task.submitter)
+				&& query.started_after.is_none_or(|x| start >= x)
+				&& query.started_before.is_none_or(|x| start < x)
+		})
+		.collect::<Vec<_>>();
+
+	match query.sort {
+		SortKey::StartTime => tasks.sort_by_key(|x| x.start_time),
+		SortKey::EndTime => tasks.sort_by_key(|x| x.status.end_time()),
+		SortKey::Submitter => tasks.sort_by(|a, b| a.submitter.cmp(&b.submitter).then(a.start_time.cmp(&b.start_time))),
+	}
+	if let SortOrder::Desc = query.order {
+		tasks.reverse();
+	}
+
+	let total = tasks.len();
+	let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+	let tasks = tasks.into_iter().skip(query.offset).take(limit).collect();
+
+	Json(TaskList {
+		total,
+		offset: query.offset,
+		limit,
+		tasks,
+	})
+}
+
+#[derive(serde::Serialize)]
+struct DrainStatus {
+	draining: bool,
+	/// safe to stop the server once this is 0
+	tasks_in_progress: usize,
+}
+
+async fn drain_status(
+	State(task_manager): State<Arc<TaskManager>>,
+	State(shutdown): State<Arc<Shutdown>>,
+) -> Json<DrainStatus> {
+	Json(DrainStatus {
+		draining: shutdown.is_draining(),
+		tasks_in_progress: task_manager.tasks_in_progress().await.len(),
+	})
+}
+
+/// Refuse new tasks and fail `/readyz`, while running tasks finish. For taking the instance out of rotation
+async fn start_draining(
+	task_manager: State<Arc<TaskManager>>,
+	shutdown: State<Arc<Shutdown>>,
+) -> Json<DrainStatus> {
+	if !shutdown.is_draining() {
+		tracing::info!("draining, no new tasks are accepted");
+	}
+	shutdown.set_draining(true);
+	drain_status(task_manager, shutdown).await
+}
+
+async fn stop_draining(
+	task_manager: State<Arc<TaskManager>>,
+	shutdown: State<Arc<Shutdown>>,
+) -> EndpointResult<Json<DrainStatus>> {
+	if shutdown.shutdown_started() {
+		return EndpointResult::Err(StatusCode::CONFLICT, Some("the server is shutting down".into()));
+	}
+	if shutdown.is_draining() {
+		tracing::info!("stopped draining, accepting tasks again");
+	}
+	shutdown.set_draining(false);
+	EndpointResult::Ok(drain_status(task_manager, shutdown).await)
+}
@@ -0,0 +1,89 @@
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use super::{reload_config, TaskManager};
+use crate::avg::DisplayDuration;
+use crate::task::{TaskId, TaskStatus};
+
+const HELP: &str = "\
+commands:
+  list          list tasks
+  cancel <id>   cancel a task
+  reload        reload the config file
+  help          show this message";
+
+/// Read admin commands from stdin. Does nothing if stdin is not a terminal.
+pub(super) fn spawn_console(task_manager: Arc<TaskManager>) {
+	if !std::io::stdin().is_terminal() {
+		return;
+	}
+
+	tokio::task::spawn(async move {
+		let mut lines = BufReader::new(tokio::io::stdin()).lines();
+		while let Ok(Some(line)) = lines.next_line().await {
+			let mut words = line.split_whitespace();
+			match (words.next(), words.next()) {
+				(None, _) => {}
+				(Some("list" | "ls"), None) => list_tasks(&task_manager).await,
+				(Some("cancel"), Some(id)) => match id.parse::<TaskId>() {
+					Ok(id) => cancel_task(&task_manager, id).await,
+					Err(_) => println!("invalid task id: {id}"),
+				},
+				(Some("reload"), None) => {
+					// the result is logged
+					let _ = reload_config().await;
+				}
+				(Some("help"), None) => println!("{HELP}"),
+				_ => println!("unknown command: {line}\n{HELP}"),
+			}
+		}
+		tracing::debug!("stdin closed, console stopped");
+	});
+}
+
+async fn list_tasks(task_manager: &TaskManager) {
+	let tasks_lock = task_manager.tasks.read().await;
+	if tasks_lock.is_empty() {
+		println!("no tasks");
+		return;
+	}
+
+	let mut tasks = tasks_lock.values().collect::<Vec<_>>();
+	tasks.sort_by_key(|x| x.start_time());
+	for task in tasks {
+		let status = match task.last_status() {
+			TaskStatus::InProgress {
+				phase,
+				progress,
+				speed,
+				phase_eta_seconds,
+				..
+			} => {
+				let eta = phase_eta_seconds
+					.map_or("unknown".to_string(), |x| DisplayDuration(Duration::from_secs_f32(x)).to_string());
+				format!("{phase} {:.1}% at {speed:.2}x, eta {eta}", progress * 100.0)
+			}
+			TaskStatus::Error(err) => format!("error: {}", err.to_string().lines().next().unwrap_or_default()),
+			TaskStatus::Completed { end_time, summary } => format!(
+				"completed at {end_time}, {} -> {} ({:.1}% removed)",
+				DisplayDuration(Duration::from_secs_f32(summary.original_duration)),
+				DisplayDuration(Duration::from_secs_f32(summary.output_duration)),
+				summary.removed_percent
+			),
+			TaskStatus::Cancelled { end_time } => format!("cancelled at {end_time}"),
+		};
+		println!("{:<20} started {} by {}  {status}", task.id, task.start_time(), task.submitter());
+	}
+}
+
+async fn cancel_task(task_manager: &TaskManager, id: TaskId) {
+	let Some(task) = task_manager.get_task(id).await else {
+		println!("no task with id {id}");
+		return;
+	};
+	task.cancel();
+	tracing::info!("cancelled task {}", id);
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use super::{Shutdown, TaskManager};
+use crate::config::{check_dir_writable, Config, CONFIG};
+use crate::disk::free_space;
+
+#[derive(serde::Serialize)]
+struct Check {
+	ok: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	message: Option<String>,
+}
+
+impl Check {
+	fn ok(message: Option<String>) -> Self {
+		Self { ok: true, message }
+	}
+
+	fn failed(message: String) -> Self {
+		Self {
+			ok: false,
+			message: Some(message),
+		}
+	}
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+	ready: bool,
+	checks: BTreeMap<&'static str, Check>,
+}
+
+/// The process is up and serving requests
+pub(super) async fn healthz() -> &'static str {
+	"ok"
+}
+
+fn check_dir(dir: &std::path::Path, min_free_space: u64) -> Check {
+	if let Err(err) = check_dir_writable(dir) {
+		return Check::failed(format!("\"{}\" is not writable: {err}", dir.display()));
+	}
+	match free_space(dir) {
+		Ok(free) if free < min_free_space => {
+			Check::failed(format!("{free} bytes free, at least {min_free_space} required"))
+		}
+		Ok(free) => Check::ok(Some(format!("{free} bytes free"))),
+		Err(err) => Check::failed(format!("failed to read free space: {err}")),
+	}
+}
+
+/// encoder, web root and data directories
+fn check_files(config: &Config) -> [(&'static str, Check); 4] {
+	let encoder = if config.encoder_found() {
+		Check::ok(None)
+	} else {
+		Check::failed(format!("ffmpeg not found at {}", config.ffmpeg_executable.display()))
+	};
+	let web_root = if config.web_dir_found() {
+		Check::ok(None)
+	} else {
+		Check::failed(format!("{} is missing index.html or completed.html", config.web_root.display()))
+	};
+	let min_free_space = config.readiness.min_free_space;
+	[
+		("encoder", encoder),
+		("web_root", web_root),
+		("inputs_dir", check_dir(&config.inputs_dir, min_free_space)),
+		("outputs_dir", check_dir(&config.outputs_dir, min_free_space)),
+	]
+}
+
+/// Whether new tasks can be accepted, with the result of each check.
+/// 503 if any of them failed
+pub(super) async fn readyz(
+	State(task_manager): State<Arc<TaskManager>>,
+	State(shutdown): State<Arc<Shutdown>>,
+) -> Response {
+	let mut checks = BTreeMap::new();
+	checks.insert(
+		"draining",
+		if shutdown.is_draining() {
+			Check::failed("not accepting new tasks".to_string())
+		} else {
+			Check::ok(None)
+		},
+	);
+	// the checks block, so they run on a copy of the config instead of under its lock
+	let config = CONFIG.read().await.clone();
+	let max_tasks_in_progress = config.readiness.max_tasks_in_progress;
+	match tokio::task::spawn_blocking(move || check_files(&config)).await {
+		Ok(file_checks) => checks.extend(file_checks),
+		Err(err) => {
+			checks.insert("files", Check::failed(format!("checks didn't finish: {err}")));
+		}
+	}
+
+	let in_progress = task_manager.tasks_in_progress().await.len();
+	let message = format!("{in_progress} of {max_tasks_in_progress} tasks in progress");
+	checks.insert(
+		"queue",
+		if in_progress < max_tasks_in_progress {
+			Check::ok(Some(message))
+		} else {
+			Check::failed(message)
+		},
+	);
+
+	let ready = checks.values().all(|x| x.ok);
+	let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+	(code, Json(Readiness { ready, checks })).into_response()
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use super::admin::tokens_match;
+use super::TaskManager;
+use crate::config::CONFIG;
+use crate::disk::dir_size;
+use crate::metrics::{MetricsWriter, METRICS};
+use crate::task::TaskStatus;
+
+/// Prometheus metrics, behind `Authorization: Bearer <metrics_token>` if one is set
+pub(super) async fn metrics(State(task_manager): State<Arc<TaskManager>>, headers: HeaderMap) -> Response {
+	let (metrics_token, inputs_dir, outputs_dir) = {
+		let config_lock = CONFIG.read().await;
+		(config_lock.metrics_token.clone(), config_lock.inputs_dir.clone(), config_lock.outputs_dir.clone())
+	};
+	if let Some(metrics_token) = metrics_token {
+		let authorized = headers
+			.get(AUTHORIZATION)
+			.and_then(|x| x.to_str().ok())
+			.and_then(|x| x.strip_prefix("Bearer "))
+			.is_some_and(|x| tokens_match(x.as_bytes(), metrics_token.as_bytes()));
+		if !authorized {
+			return (StatusCode::UNAUTHORIZED, "invalid metrics token").into_response();
+		}
+	}
+
+	// tasks run as soon as they're submitted, so unfinished tasks are the queue
+	let mut in_progress = BTreeMap::new();
+	for task in task_manager.tasks.read().await.values() {
+		if let TaskStatus::InProgress { phase, .. } = task.last_status() {
+			*in_progress.entry(phase.to_string()).or_insert(0) += 1;
+		}
+	}
+
+	let mut writer = MetricsWriter::new();
+	METRICS.write(&mut writer);
+	writer.labeled_gauge(
+		"voice_tasks_in_progress",
+		"Unfinished tasks, by phase",
+		"phase",
+		in_progress.iter().map(|(phase, count)| (phase.as_str(), *count)),
+	);
+	writer.labeled_gauge(
+		"voice_disk_usage_bytes",
+		"Size of stored task files",
+		"dir",
+		[("inputs", dir_size(&inputs_dir).await), ("outputs", dir_size(&outputs_dir).await)],
+	);
+
+	([(CONTENT_TYPE, "text/plain; version=0.0.4")], writer.finish()).into_response()
+}
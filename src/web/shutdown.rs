@@ -0,0 +1,213 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use super::TaskManager;
+use crate::config::CONFIG;
+use crate::task::{ConversionSummary, Task, TaskId, TaskStatus};
+
+/// While draining, no new tasks are accepted but everything else works as usual.
+/// Shutdown starts draining and stops the server once tasks are done
+pub(super) struct Shutdown {
+	draining: AtomicBool,
+	shutdown_started: AtomicBool,
+	stopping: watch::Sender<bool>,
+}
+
+impl Shutdown {
+	pub(super) fn new() -> Self {
+		Self {
+			draining: AtomicBool::new(false),
+			shutdown_started: AtomicBool::new(false),
+			stopping: watch::channel(false).0,
+		}
+	}
+
+	pub(super) fn is_draining(&self) -> bool {
+		self.draining.load(Ordering::Relaxed)
+	}
+
+	pub(super) fn set_draining(&self, draining: bool) {
+		self.draining.store(draining, Ordering::Relaxed);
+	}
+
+	/// whether [`Shutdown::begin`] was called, draining can't be stopped after
+	pub(super) fn shutdown_started(&self) -> bool {
+		self.shutdown_started.load(Ordering::Relaxed)
+	}
+
+	/// Resolves once the server is about to stop, long running responses should end then
+	pub(super) async fn stopping(&self) {
+		let mut rx = self.stopping.subscribe();
+		// the sender lives as long as `self`
+		let _ = rx.wait_for(|x| *x).await;
+	}
+
+	/// Resolves `shutdown.grace_period_seconds` after [`Shutdown::stopping`]
+	pub(super) async fn grace_period_over(&self) {
+		self.stopping().await;
+		let grace_period = Duration::from_secs(CONFIG.read().await.shutdown.grace_period_seconds);
+		tokio::time::sleep(grace_period).await;
+	}
+
+	/// Drain, wait up to `shutdown.task_timeout_seconds` for running tasks, then signal [`Shutdown::stopping`].
+	/// Returns the grace period for open connections
+	pub(super) async fn begin(&self, task_manager: &TaskManager) -> Duration {
+		self.shutdown_started.store(true, Ordering::Relaxed);
+		self.set_draining(true);
+		let config = CONFIG.read().await.shutdown.clone();
+
+		let in_progress = task_manager.tasks_in_progress().await.len();
+		if in_progress > 0 && config.task_timeout_seconds > 0 {
+			tracing::info!(
+				"waiting up to {}s for {} running tasks to finish",
+				config.task_timeout_seconds,
+				in_progress
+			);
+			let timeout = Duration::from_secs(config.task_timeout_seconds);
+			if tokio::time::timeout(timeout, task_manager.wait_for_running_tasks()).await.is_err() {
+				tracing::warn!("tasks still running after {}s, cancelling them", config.task_timeout_seconds);
+			}
+		}
+
+		self.stopping.send_replace(true);
+		Duration::from_secs(config.grace_period_seconds)
+	}
+}
+
+/// Resolves on SIGTERM or SIGINT. A second one exits right away
+pub(super) async fn signal_received() {
+	let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+	let mut sigint = signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
+	tokio::select! {
+		_ = sigterm.recv() => tracing::info!("SIGTERM received, shutting down"),
+		_ = sigint.recv() => tracing::info!("SIGINT received, shutting down"),
+	}
+
+	tokio::task::spawn(async move {
+		tokio::select! {
+			_ = sigterm.recv() => {}
+			_ = sigint.recv() => {}
+		}
+		tracing::warn!("received another signal, exiting without cleaning up");
+		std::process::exit(1);
+	});
+}
+
+/// A completed task in the state file
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedTask {
+	id: TaskId,
+	submitter: String,
+	#[serde(with = "time::serde::timestamp")]
+	start_time: time::OffsetDateTime,
+	#[serde(with = "time::serde::timestamp")]
+	end_time: time::OffsetDateTime,
+	keep_for_seconds: Option<i64>,
+	summary: ConversionSummary,
+}
+
+/// Stop every task that's still running, and save completed ones to `shutdown.state_file`.
+/// Then wait up to `shutdown.grace_period_seconds` for webhook deliveries, including those of the stopped tasks
+pub(super) async fn finish(task_manager: &TaskManager) {
+	let config = CONFIG.read().await.shutdown.clone();
+	let state_file = config.state_file;
+	let tasks = std::mem::take(&mut *task_manager.tasks.write().await);
+
+	let saved = tasks
+		.values()
+		.filter_map(|task| match task.last_status() {
+			TaskStatus::Completed { end_time, summary } => Some(SavedTask {
+				id: task.id,
+				submitter: task.submitter().to_string(),
+				start_time: task.start_time(),
+				end_time,
+				keep_for_seconds: task.keep_for().map(|x| x.whole_seconds()),
+				summary,
+			}),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	if let Some(state_file) = state_file {
+		match save_tasks(&state_file, &saved).await {
+			Ok(()) => tracing::info!("saved {} completed tasks to {}", saved.len(), state_file.display()),
+			Err(err) => tracing::error!("failed to save tasks to {}: {}", state_file.display(), err),
+		}
+	}
+
+	for task in tasks.into_values() {
+		// also waits for the encoder to be killed
+		task.stop().await;
+	}
+
+	let callbacks = &task_manager.callbacks;
+	callbacks.close();
+	if !callbacks.is_empty() {
+		tracing::info!("waiting up to {}s for {} callbacks", config.grace_period_seconds, callbacks.len());
+		let grace_period = Duration::from_secs(config.grace_period_seconds);
+		if tokio::time::timeout(grace_period, callbacks.wait()).await.is_err() {
+			tracing::warn!("dropping {} callbacks that weren't delivered in time", callbacks.len());
+		}
+	}
+}
+
+async fn save_tasks(state_file: &Path, tasks: &[SavedTask]) -> std::io::Result<()> {
+	// written next to it first, so a crash can't leave half a file behind
+	let partial = state_file.with_extension("part");
+	tokio::fs::write(&partial, serde_json::to_vec(tasks).unwrap()).await?;
+	tokio::fs::rename(&partial, state_file).await
+}
+
+/// Load the tasks saved by [`finish`] whose outputs still exist
+pub(super) async fn restore_tasks(task_manager: &TaskManager) {
+	let (state_file, outputs_dir) = {
+		let config_lock = CONFIG.read().await;
+		(config_lock.shutdown.state_file.clone(), config_lock.outputs_dir.clone())
+	};
+	let Some(state_file) = state_file else {
+		return;
+	};
+	let data = match tokio::fs::read(&state_file).await {
+		Ok(x) => x,
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+		Err(err) => {
+			tracing::error!("failed to read {}: {}", state_file.display(), err);
+			return;
+		}
+	};
+	let saved = match serde_json::from_slice::<Vec<SavedTask>>(&data) {
+		Ok(x) => x,
+		Err(err) => {
+			tracing::error!("ignoring invalid {}: {}", state_file.display(), err);
+			return;
+		}
+	};
+
+	let mut tasks_lock = task_manager.tasks.write().await;
+	for saved in saved {
+		let stored = task_manager.storage.s3_output(saved.id).is_some();
+		if !stored && !outputs_dir.join(saved.id.to_string()).exists() {
+			tracing::info!("not restoring task {}, its output is gone", saved.id);
+			continue;
+		}
+		let task = Task::restored(
+			saved.id,
+			saved.submitter,
+			saved.start_time,
+			saved.keep_for_seconds.map(time::Duration::seconds),
+			saved.end_time,
+			saved.summary,
+		);
+		tasks_lock.insert(saved.id, task);
+	}
+	tracing::info!("restored {} completed tasks from {}", tasks_lock.len(), state_file.display());
+	drop(tasks_lock);
+
+	// restored once, a later crash shouldn't bring back tasks that have expired since
+	if let Err(err) = tokio::fs::remove_file(&state_file).await {
+		tracing::warn!("failed to remove {}: {}", state_file.display(), err);
+	}
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::{self, WebSocket};
+use axum::extract::{State, WebSocketUpgrade};
+use axum::response::Response;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::{close_code, close_ws, heartbeat_config, AppState, EndpointResult, TaskManager};
+use crate::config::CONFIG;
+use crate::metrics::METRICS;
+use crate::task::{TaskId, TaskStatus, TaskUpdateMessage};
+
+/// Ids are sent as strings, since they don't fit into a javascript number, but numbers are accepted too
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonTaskId {
+	String(String),
+	Number(TaskId),
+}
+
+impl JsonTaskId {
+	fn parse(&self) -> Option<TaskId> {
+		match self {
+			Self::String(x) => x.parse().ok(),
+			Self::Number(x) => Some(*x),
+		}
+	}
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type", content = "data")]
+enum ClientMessage {
+	Subscribe(Vec<JsonTaskId>),
+	Unsubscribe(Vec<JsonTaskId>),
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type", content = "data")]
+enum ServerMessage<'a> {
+	Update {
+		task_id: String,
+		status: &'a TaskStatus,
+	},
+	Error(String),
+}
+
+/// Watch any number of tasks over one websocket.
+///
+/// The client sends `{"type": "subscribe", "data": ["<id>", ...]}` and `unsubscribe` alike,
+/// and receives `{"type": "update", "data": {"task_id": "<id>", "status": <TaskStatus>}}`,
+/// starting with each task's current status. Subscriptions end by themselves once the task stops.
+pub(super) async fn tasks_ws(
+	ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+	state: State<AppState>,
+) -> EndpointResult<Response> {
+	let ws = match ws {
+		Ok(x) => x,
+		Err(err) => {
+			tracing::info!("ws upgrade rejected: {}", err);
+			return EndpointResult::Err(err.status(), None);
+		}
+	};
+
+	EndpointResult::Ok(
+		ws.on_failed_upgrade(|_| tracing::info!("ws upgrade failed"))
+			.on_upgrade(move |ws| tasks_ws_handler(ws, state.0)),
+	)
+}
+
+fn to_message(message: &ServerMessage) -> ws::Message {
+	ws::Message::Text(serde_json::to_string(message).unwrap())
+}
+
+fn update_message((task_id, status): &TaskUpdateMessage) -> ws::Message {
+	to_message(&ServerMessage::Update {
+		task_id: task_id.to_string(),
+		status,
+	})
+}
+
+async fn tasks_ws_handler(ws: WebSocket, state: AppState) {
+	tracing::info!("tasks ws connected");
+	let _active = METRICS.active_websockets.track();
+	let (mut sender, mut receiver) = ws.split();
+
+	let (ping_interval, idle_timeout) = heartbeat_config().await;
+	let mut ping = tokio::time::interval(ping_interval);
+	let mut last_seen = tokio::time::Instant::now();
+
+	// forwarders send task updates here, one per subscribed task
+	let (updates_tx, mut updates_rx) = mpsc::channel::<TaskUpdateMessage>(64);
+	let mut subscriptions = HashMap::<TaskId, JoinHandle<()>>::new();
+
+	// `None` if there's no point in sending a close frame
+	let close = loop {
+		let message = tokio::select! {
+			// updates first and the shutdown last, so final statuses aren't lost to a shutdown right after
+			biased;
+			Some(update) = updates_rx.recv() => {
+				// may have been queued before unsubscribing
+				if !subscriptions.contains_key(&update.0) {
+					continue;
+				}
+				if !matches!(update.1, TaskStatus::InProgress { .. }) {
+					subscriptions.remove(&update.0);
+				}
+				if sender.send(update_message(&update)).await.is_err() {
+					break None;
+				}
+				continue;
+			}
+			message = receiver.next() => match message {
+				Some(Ok(ws::Message::Close(_))) | Some(Err(_)) | None => break None,
+				Some(Ok(message)) => {
+					last_seen = tokio::time::Instant::now();
+					match message {
+						ws::Message::Text(text) => text,
+						_ => continue,
+					}
+				}
+			},
+			_ = ping.tick() => {
+				if last_seen.elapsed() > idle_timeout {
+					break Some((close_code::IDLE_TIMEOUT, "idle timeout"));
+				}
+				if sender.send(ws::Message::Ping(Vec::new())).await.is_err() {
+					break None;
+				}
+				continue;
+			}
+			_ = state.shutdown.stopping() => break Some((close_code::SHUTTING_DOWN, "server shutting down")),
+		};
+
+		let reply = match serde_json::from_str::<ClientMessage>(&message) {
+			Ok(ClientMessage::Subscribe(ids)) => {
+				subscribe(&state.task_manager, &mut subscriptions, &updates_tx, &ids).await.err()
+			}
+			Ok(ClientMessage::Unsubscribe(ids)) => {
+				for id in ids.iter().filter_map(JsonTaskId::parse) {
+					if let Some(forwarder) = subscriptions.remove(&id) {
+						forwarder.abort();
+					}
+				}
+				None
+			}
+			Err(err) => Some(format!("invalid message: {err}")),
+		};
+		if let Some(reply) = reply {
+			if sender.send(to_message(&ServerMessage::Error(reply))).await.is_err() {
+				break None;
+			}
+		}
+	};
+
+	for forwarder in subscriptions.into_values() {
+		forwarder.abort();
+	}
+	if let Some((code, reason)) = close {
+		close_ws(sender, receiver, code, reason).await;
+	}
+	tracing::info!("tasks ws disconnected");
+}
+
+/// Subscribe to every task in `ids`, stopping at the first one that fails
+async fn subscribe(
+	task_manager: &TaskManager,
+	subscriptions: &mut HashMap<TaskId, JoinHandle<()>>,
+	updates_tx: &mpsc::Sender<TaskUpdateMessage>,
+	ids: &[JsonTaskId],
+) -> Result<(), String> {
+	let max_subscriptions = CONFIG.read().await.websocket.max_subscriptions;
+
+	for id in ids {
+		let task_id = id.parse().ok_or("invalid task id")?;
+		if subscriptions.contains_key(&task_id) {
+			continue;
+		}
+		if subscriptions.len() >= max_subscriptions {
+			return Err(format!("subscription limit of {max_subscriptions} reached"));
+		}
+		let mut rx = match task_manager.get_task(task_id).await {
+			Some(task) => task.subscribe(),
+			None => return Err(format!("task {task_id} not found")),
+		};
+
+		let updates_tx = updates_tx.clone();
+		let forwarder = tokio::task::spawn(async move {
+			loop {
+				// starts with the current status
+				let status = rx.borrow_and_update().status.clone();
+				let finished = !matches!(status, TaskStatus::InProgress { .. });
+				if updates_tx.send((task_id, status)).await.is_err() || finished || rx.changed().await.is_err() {
+					break;
+				}
+			}
+		});
+		subscriptions.insert(task_id, forwarder);
+	}
+	Ok(())
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::{Shutdown, TaskManager};
+use crate::config::CONFIG;
+use crate::task::TaskStatus;
+
+/// Size and modification time, a file is picked up once they stop changing
+type FileState = (u64, SystemTime);
+
+/// Regular, non-hidden files directly in `dir`
+async fn scan(dir: &Path) -> std::io::Result<HashMap<PathBuf, FileState>> {
+	let mut files = HashMap::new();
+	let mut entries = tokio::fs::read_dir(dir).await?;
+	while let Some(entry) = entries.next_entry().await? {
+		if entry.file_name().to_string_lossy().starts_with('.') {
+			continue;
+		}
+		let metadata = entry.metadata().await?;
+		if metadata.is_file() {
+			files.insert(entry.path(), (metadata.len(), metadata.modified()?));
+		}
+	}
+	Ok(files)
+}
+
+/// Where the output for `input` is written, `a.mov` becomes `a.mp4`
+fn output_path(output_dir: &Path, input: &Path) -> PathBuf {
+	output_dir.join(Path::new(input.file_name().unwrap_or_default()).with_extension("mp4"))
+}
+
+/// Turn files dropped into `watch.input_dir` into tasks, see [`crate::config::WatchConfig`]
+pub(super) fn spawn_watcher(task_manager: Arc<TaskManager>, shutdown: Arc<Shutdown>) {
+	tokio::task::spawn(async move {
+		let mut last_scan = HashMap::new();
+		// outputs of tasks that are still running
+		let claimed = Arc::new(Mutex::new(HashSet::new()));
+		// inputs left alone because of their output name, only logged once
+		let mut collided = HashSet::new();
+
+		loop {
+			let watch_config = CONFIG.read().await.watch.clone();
+			if !watch_config.enabled {
+				last_scan.clear();
+				// check again later, in case it's enabled by a config reload
+				tokio::time::sleep(Duration::from_secs(60)).await;
+				continue;
+			}
+			if shutdown.is_draining() {
+				// files are picked up again once draining stops, or by the next instance
+				last_scan.clear();
+				tokio::time::sleep(Duration::from_secs(watch_config.poll_interval_seconds)).await;
+				continue;
+			}
+
+			match scan(&watch_config.input_dir).await {
+				Ok(files) => {
+					let mut ready = files.iter().filter(|(path, state)| last_scan.get(*path) == Some(*state)).collect::<Vec<_>>();
+					ready.sort();
+					for (path, _) in ready {
+						let destination = output_path(&watch_config.output_dir, path);
+						// e.g. `a.mov` and `a.mkv`, or an output from earlier
+						if destination.exists() || !claimed.lock().unwrap().insert(destination.clone()) {
+							if collided.insert(path.clone()) {
+								tracing::warn!(
+									"watch folder: not processing {}, {} already exists or is being written. rename it to process it",
+									path.display(),
+									destination.display()
+								);
+							}
+							continue;
+						}
+						ingest(&task_manager, path, destination, &claimed).await;
+					}
+					collided.retain(|x| files.contains_key(x));
+					last_scan = files;
+				}
+				Err(err) => tracing::error!("failed to scan watch folder {}: {}", watch_config.input_dir.display(), err),
+			}
+
+			tokio::time::sleep(Duration::from_secs(watch_config.poll_interval_seconds)).await;
+		}
+	});
+}
+
+/// Start a task for `path`, writing its output to `destination`, which is claimed until then
+async fn ingest(
+	task_manager: &Arc<TaskManager>,
+	path: &Path,
+	destination: PathBuf,
+	claimed: &Arc<Mutex<HashSet<PathBuf>>>,
+) {
+	let task_id = match task_manager.new_task_from_file(path, "watch".to_string()).await {
+		Ok(x) => x,
+		Err(err) => {
+			tracing::error!("failed to create task for {}: {}", path.display(), err);
+			claimed.lock().unwrap().remove(&destination);
+			return;
+		}
+	};
+	tracing::info!("watch folder: {} is task {}", path.display(), task_id);
+
+	let file_name = path.file_name().unwrap_or_default().to_owned();
+	tokio::task::spawn({
+		let (task_manager, claimed) = (task_manager.clone(), claimed.clone());
+		async move {
+			let status = task_manager.wait_for_task(task_id).await;
+			let result = match status {
+				Some(TaskStatus::Completed { .. }) => {
+					let output = CONFIG.read().await.outputs_dir.join(task_id.to_string());
+					Some(task_manager.storage.copy_output(task_id, &output, &destination).await.map(|_| destination.clone()))
+				}
+				Some(TaskStatus::Error(err)) => {
+					let error_file = destination.with_file_name(format!("{}.error.txt", file_name.to_string_lossy()));
+					Some(tokio::fs::write(&error_file, err.to_string()).await.map(|_| error_file))
+				}
+				// cancelled, or removed before finishing
+				_ => None,
+			};
+			claimed.lock().unwrap().remove(&destination);
+			match result {
+				Some(Ok(written)) => tracing::info!("watch folder: wrote {}", written.display()),
+				Some(Err(err)) => tracing::error!("watch folder: failed to write result of task {}: {}", task_id, err),
+				None => {}
+			}
+		}
+	});
+}
@@ -0,0 +1,261 @@
+use std::{fmt::Display, time::Duration};
+
+use rand::Rng;
+use reqwest::{header::CONTENT_TYPE, StatusCode, Url};
+
+use crate::{
+	config::{WebhookConfig, CONFIG},
+	task::{TaskId, TaskStatus},
+};
+
+/// backoff doesn't grow past this
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 10);
+
+#[derive(Debug)]
+pub enum CallbackUrlError {
+	Invalid(String),
+	NotAllowed,
+}
+
+impl Display for CallbackUrlError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Invalid(err) => write!(f, "invalid callback url: {err}"),
+			Self::NotAllowed => write!(f, "callback url is not allowed"),
+		}
+	}
+}
+
+/// `url` is on the same origin as `allowed` and its path is `allowed`'s path or below it,
+/// so `/hooks` allows `/hooks/a` but not `/hooksevil`
+fn url_allowed(url: &Url, allowed: &Url) -> bool {
+	let prefix = allowed.path().trim_end_matches('/');
+	url.scheme() == allowed.scheme()
+		&& url.host() == allowed.host()
+		&& url.port_or_known_default() == allowed.port_or_known_default()
+		&& url
+			.path()
+			.strip_prefix(prefix)
+			.is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Parse a callback url and check it against `webhook.allowed_urls`
+pub async fn check_callback_url(url: &str) -> Result<Url, CallbackUrlError> {
+	let url = Url::parse(url).map_err(|x| CallbackUrlError::Invalid(x.to_string()))?;
+	if !url.username().is_empty() || url.password().is_some() {
+		return Err(CallbackUrlError::NotAllowed);
+	}
+
+	let config_lock = CONFIG.read().await;
+	let allowed = config_lock
+		.webhook
+		.allowed_urls
+		.iter()
+		.filter_map(|x| Url::parse(x).ok())
+		.any(|x| url_allowed(&url, &x));
+	if allowed {
+		Ok(url)
+	} else {
+		Err(CallbackUrlError::NotAllowed)
+	}
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+	/// string, since ids don't fit into a javascript number
+	task_id: String,
+	status: &'a TaskStatus,
+}
+
+fn event_name(status: &TaskStatus) -> &'static str {
+	match status {
+		TaskStatus::InProgress { .. } => "inProgress",
+		TaskStatus::Error(_) => "error",
+		TaskStatus::Completed { .. } => "completed",
+		TaskStatus::Cancelled { .. } => "cancelled",
+	}
+}
+
+/// hex encoded hmac-sha256 of `{timestamp}.{body}`
+fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+	let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+	let mut context = ring::hmac::Context::with_key(&key);
+	context.update(timestamp.to_string().as_bytes());
+	context.update(b".");
+	context.update(body);
+	context.sign().as_ref().iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// Worth trying again later
+fn is_transient(status: StatusCode) -> bool {
+	status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// POST the task's final status to `url`, retrying with exponential backoff.
+///
+/// Receivers verify `X-Voice-Signature: sha256=<hex>`, the hmac of `{X-Voice-Timestamp}.{body}`
+/// keyed with `webhook.secret`. `X-Voice-Delivery` stays the same across retries.
+/// Delivery is best effort, on shutdown retries still pending after `shutdown.grace_period_seconds` are dropped.
+pub async fn deliver(url: Url, task_id: TaskId, status: TaskStatus) {
+	let webhook_config = CONFIG.read().await.webhook.clone();
+	deliver_with(&webhook_config, url, task_id, status).await;
+}
+
+async fn deliver_with(webhook_config: &WebhookConfig, url: Url, task_id: TaskId, status: TaskStatus) {
+	let Some(secret) = &webhook_config.secret else {
+		tracing::warn!("not sending callback for task {}: webhook.secret is not set", task_id);
+		return;
+	};
+
+	let client = match reqwest::Client::builder()
+		.user_agent(concat!("voice/", env!("CARGO_PKG_VERSION")))
+		.timeout(Duration::from_secs(webhook_config.timeout_seconds))
+		// a redirect would take the signed payload past `allowed_urls`
+		.redirect(reqwest::redirect::Policy::none())
+		.build()
+	{
+		Ok(x) => x,
+		Err(err) => {
+			tracing::error!("failed to create http client for callbacks: {}", err);
+			return;
+		}
+	};
+
+	let body = serde_json::to_vec(&Payload {
+		task_id: task_id.to_string(),
+		status: &status,
+	})
+	.unwrap();
+	let delivery_id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+	let mut backoff = Duration::from_secs(webhook_config.initial_backoff_seconds);
+
+	for attempt in 1..=webhook_config.max_attempts {
+		let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+		let result = client
+			.post(url.clone())
+			.header(CONTENT_TYPE, "application/json")
+			.header("X-Voice-Event", event_name(&status))
+			.header("X-Voice-Delivery", &delivery_id)
+			.header("X-Voice-Timestamp", timestamp)
+			.header("X-Voice-Signature", format!("sha256={}", sign(secret, timestamp, &body)))
+			.body(body.clone())
+			.send()
+			.await;
+
+		match result {
+			Ok(response) if response.status().is_success() => {
+				tracing::info!("delivered callback for task {} to {}", task_id, url);
+				return;
+			}
+			Ok(response) if !is_transient(response.status()) => {
+				tracing::warn!("callback for task {} rejected by {}: {}", task_id, url, response.status());
+				return;
+			}
+			Ok(response) => {
+				tracing::info!("callback for task {} attempt {} failed: {}", task_id, attempt, response.status())
+			}
+			Err(err) => tracing::info!("callback for task {} attempt {} failed: {}", task_id, attempt, err),
+		}
+
+		if attempt < webhook_config.max_attempts {
+			tokio::time::sleep(backoff).await;
+			backoff = (backoff * 2).min(MAX_BACKOFF);
+		}
+	}
+	tracing::warn!("giving up on callback for task {} to {}", task_id, url);
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::{
+		body::Bytes,
+		http::HeaderMap,
+		response::Redirect,
+		routing::post,
+		Router,
+	};
+	use tokio::sync::mpsc;
+
+	use super::*;
+
+	#[test]
+	fn allowed_path_matches_whole_segments() {
+		let allowed = Url::parse("https://backend.example.com/hooks").unwrap();
+		let check = |url: &str| url_allowed(&Url::parse(url).unwrap(), &allowed);
+		assert!(check("https://backend.example.com/hooks"));
+		assert!(check("https://backend.example.com/hooks/voice"));
+		assert!(!check("https://backend.example.com/hooksevil"));
+		assert!(!check("https://backend.example.com/other"));
+		assert!(!check("http://backend.example.com/hooks"));
+		assert!(!check("https://backend.example.com:8443/hooks"));
+
+		let allowed = Url::parse("https://backend.example.com/hooks/").unwrap();
+		assert!(url_allowed(&Url::parse("https://backend.example.com/hooks/a").unwrap(), &allowed));
+		assert!(!url_allowed(&Url::parse("https://backend.example.com/hooksevil").unwrap(), &allowed));
+	}
+
+	/// A receiver on a local port, sending every request to `/hook` to the returned channel
+	fn receiver() -> (Url, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+		let (tx, rx) = mpsc::unbounded_channel();
+		let app = Router::new()
+			.route(
+				"/hook",
+				post(move |headers: HeaderMap, body: Bytes| async move {
+					let _ = tx.send((headers, body));
+				}),
+			)
+			.route("/moved", post(|| async { Redirect::temporary("/hook") }));
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+		let server = axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service());
+		tokio::spawn(server);
+		(url, rx)
+	}
+
+	fn config() -> WebhookConfig {
+		WebhookConfig {
+			secret: Some("hooksecret".to_string()),
+			max_attempts: 1,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn signature_matches_known_value() {
+		// python3 -c 'import hmac; print(hmac.new(b"hooksecret", b"1700000000.{\"task_id\":\"42\"}", "sha256").hexdigest())'
+		assert_eq!(
+			sign("hooksecret", 1_700_000_000, br#"{"task_id":"42"}"#),
+			"917f994805a8eba1cef3404f6bc6ed4a72ee0bc7dde4a4740ef83355f8cfa72b"
+		);
+	}
+
+	#[tokio::test]
+	async fn delivers_signed_payload() {
+		let (url, mut rx) = receiver();
+		let end_time = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+		deliver_with(&config(), url.join("hook").unwrap(), 42, TaskStatus::Cancelled { end_time }).await;
+
+		let (headers, body) = rx.try_recv().expect("callback wasn't delivered");
+		let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+		assert_eq!(header("x-voice-event"), "cancelled");
+		assert_eq!(header("content-type"), "application/json");
+		// checked the way a receiver would, without `sign`
+		let signature = header("x-voice-signature");
+		let hex = signature.strip_prefix("sha256=").unwrap();
+		let signature =
+			(0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect::<Vec<_>>();
+		let signed = [header("x-voice-timestamp").as_bytes(), b".", &body].concat();
+		let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"hooksecret");
+		assert!(ring::hmac::verify(&key, &signed, &signature).is_ok());
+		let payload = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+		assert_eq!(payload["task_id"], "42");
+	}
+
+	#[tokio::test]
+	async fn redirects_are_not_followed() {
+		let (url, mut rx) = receiver();
+		let end_time = time::OffsetDateTime::now_utc();
+		deliver_with(&config(), url.join("moved").unwrap(), 42, TaskStatus::Cancelled { end_time }).await;
+		assert!(rx.try_recv().is_err());
+	}
+}